@@ -0,0 +1,11 @@
+/* Interned `blog!` format strings. The section isn't allocated: it only
+   exists in the ELF file for the host decoder, and the offset of a string
+   in it is its ID. The leading pad byte keeps the IDs non-null. */
+SECTIONS
+{
+  .blog_strings 0 (INFO) :
+  {
+    BYTE(0);
+    KEEP(*(.blog_strings .blog_strings.*));
+  }
+}
@@ -29,6 +29,25 @@ fn main() {
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
 
+    // The log filter compiled in, see `log_filter.rs`.
+    println!("cargo:rerun-if-env-changed=PICO_LOG");
+    // The line format compiled in, see `log_format.rs`.
+    println!("cargo:rerun-if-env-changed=PICO_LOG_FORMAT");
+    // The experiments to run, see `registry.rs`.
+    println!("cargo:rerun-if-env-changed=PICO_EXPERIMENTS");
+    // The result format compiled in, see `report.rs`.
+    println!("cargo:rerun-if-env-changed=PICO_REPORT");
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
+
+    // Keep the interned `blog!` format strings out of flash, see `blog.rs`.
+    if env::var_os("CARGO_FEATURE_BINARY_LOG").is_some() {
+        File::create(out.join("blog.x"))
+            .unwrap()
+            .write_all(include_bytes!("blog.x"))
+            .unwrap();
+        println!("cargo:rerun-if-changed=blog.x");
+        println!("cargo:rustc-link-arg-bins=-Tblog.x");
+    }
 }
@@ -0,0 +1,92 @@
+//! Render a binary log captured from the firmware built with
+//! `--features binary-log`:
+//!
+//! ```sh
+//! cargo run --bin blog-decode -- [--format <fields>] [--color] [--source-path] <elf> [<capture>]
+//! ```
+//!
+//! `--format` takes the fields like `PICO_LOG_FORMAT` does for the text
+//! logger, e.g. `secs,level,module`, see `src/log_format.rs` in the
+//! firmware. `--color` and `--source-path` add to it.
+//!
+//! The capture is read from stdin if not given, or can be the serial port
+//! device itself once it's set to the right baud rate.
+
+use pico_pio_dma_host::blog::Decoder;
+use pico_pio_dma_host::blog::Item;
+use pico_pio_dma_host::blog::LineFormat;
+use pico_pio_dma_host::blog::Renderer;
+use pico_pio_dma_host::blog::Strings;
+use std::io::Read;
+use std::io::Write;
+
+const USAGE: &str =
+    "usage: blog-decode [--format <fields>] [--color] [--source-path] <elf> [<capture>]";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut format = LineFormat::default();
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                // Keep flags given before it.
+                let (color, source_path) = (format.color, format.source_path);
+                format = LineFormat::parse(&args.next().ok_or(USAGE)?)?;
+                format.color |= color;
+                format.source_path |= source_path;
+            }
+            "--color" => format.color = true,
+            "--source-path" => format.source_path = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let (elf, capture) = match paths.as_slice() {
+        [elf] => (elf, None),
+        [elf, capture] => (elf, Some(capture)),
+        _ => return Err(USAGE.into()),
+    };
+
+    let strings = Strings::load(&std::fs::read(elf)?)?;
+    let mut input: Box<dyn Read> = match capture {
+        Some(path) if path != "-" => Box::new(std::fs::File::open(path)?),
+        _ => Box::new(std::io::stdin()),
+    };
+
+    let mut renderer = Renderer::new(format);
+    let mut decoder = Decoder::new();
+    let mut stdout = std::io::stdout().lock();
+    let mut buf = [0u8; 4096];
+    let mut corrupt = 0;
+    loop {
+        let n = input.read(&mut buf)?;
+        let eof = n == 0;
+        decoder.push(&buf[..n]);
+
+        while let Some(item) = decoder.next(eof) {
+            match item {
+                Item::Record(record) => writeln!(stdout, "{}", renderer.render(&record, &strings))?,
+                Item::Raw(bytes) => stdout.write_all(&bytes)?,
+                Item::Corrupt(e) => {
+                    corrupt += 1;
+                    eprintln!("blog-decode: skipping corrupt frame: {e}");
+                }
+            }
+        }
+        stdout.flush()?;
+
+        if eof {
+            break;
+        }
+    }
+
+    if corrupt != 0 {
+        eprintln!("blog-decode: {corrupt} corrupt frames");
+    }
+    Ok(())
+}
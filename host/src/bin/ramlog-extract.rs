@@ -0,0 +1,95 @@
+//! Pull the RAM log ring out of a raw dump of the target's RAM:
+//!
+//! ```sh
+//! cargo run --bin ramlog-extract -- [--boot] <ram.bin>
+//! ```
+//!
+//! Prints the records oldest first, with `--boot` only those logged since
+//! the last boot. Pipe through `blog-decode` if the firmware was built with
+//! `binary-log`. See `src/ram_log.rs` in the firmware for the layout.
+
+use std::io::Write;
+
+const RAM_LOG_MAGIC: u32 = 0x474f_4c52;
+const HEADER_WORDS: usize = 7;
+
+const USAGE: &str = "usage: ramlog-extract [--boot] <ram.bin>";
+
+struct Header {
+    size: u32,
+    written: u32,
+    boot_start: u32,
+    boots: u32,
+}
+
+fn word(dump: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        dump.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn parse_header(dump: &[u8], offset: usize) -> Option<Header> {
+    let words: Vec<u32> = (0..HEADER_WORDS)
+        .map(|i| word(dump, offset + i * 4))
+        .collect::<Option<_>>()?;
+    let [magic, magic_inv, size, written, boot_start, boots, check] = words[..] else {
+        return None;
+    };
+    if magic != RAM_LOG_MAGIC
+        || magic_inv != !RAM_LOG_MAGIC
+        || check != magic ^ magic_inv ^ size ^ written ^ boot_start ^ boots
+    {
+        return None;
+    }
+    Some(Header {
+        size,
+        written,
+        boot_start,
+        boots,
+    })
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut boot_only = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--boot" => boot_only = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let dump = std::fs::read(path.ok_or(USAGE)?)?;
+
+    let (offset, header) = (0..dump.len())
+        .step_by(4)
+        .find_map(|offset| Some((offset, parse_header(&dump, offset)?)))
+        .ok_or("no RAM log header in the dump")?;
+    let data_start = offset + HEADER_WORDS * 4;
+    let data = dump
+        .get(data_start..data_start + header.size as usize)
+        .ok_or("the dump ends in the middle of the RAM log")?;
+
+    let oldest = header.written.saturating_sub(header.size);
+    let start = if boot_only {
+        header.boot_start.max(oldest)
+    } else {
+        oldest
+    };
+    eprintln!(
+        "ramlog-extract: header at {offset:#x}, boot {}, {} bytes of {} written",
+        header.boots,
+        header.written - start,
+        header.written
+    );
+
+    let bytes: Vec<u8> = (start..header.written)
+        .map(|pos| data[pos as usize % data.len()])
+        .collect();
+    std::io::stdout().write_all(&bytes)?;
+    Ok(())
+}
@@ -0,0 +1,263 @@
+//! Run the firmware's tests over its serial port and write a JUnit XML
+//! report:
+//!
+//! ```sh
+//! cargo run --bin test-runner -- [--baud <rate>] [--command <line>]... \
+//!     [--timeout <secs>] [--test-timeout <secs>] [--junit <path>] [--quiet] <device>
+//! ```
+//!
+//! Without `--command` it waits for the run the firmware does at boot, so
+//! start it and then reset the board. With them it waits for the shell
+//! prompt and types the commands one by one, e.g. `--command "set size
+//! 1024" --command "run dma*" --command results`. Everything received is
+//! copied to stdout unless `--quiet`.
+//!
+//! The results are read in either `PICO_REPORT` format from the text log,
+//! not from a `binary-log` build. A test that takes longer than
+//! `--test-timeout` (10 s) to report is failed as hung, and the whole run
+//! may take `--timeout` (120 s). Exits with 1 if anything failed.
+//!
+//! `<device>` can be a pty standing in for the board, to try it out.
+
+use pico_pio_dma_host::results;
+use pico_pio_dma_host::results::Event;
+use pico_pio_dma_host::results::Status;
+use pico_pio_dma_host::results::TestCase;
+use std::io::Read;
+use std::io::Write;
+use std::process::ExitCode;
+use std::time::Duration;
+use std::time::Instant;
+
+const USAGE: &str = "usage: test-runner [--baud <rate>] [--command <line>]... \
+    [--timeout <secs>] [--test-timeout <secs>] [--junit <path>] [--quiet] <device>";
+
+const PROMPT: &str = "> ";
+/// Ctrl-U, clears whatever is on the shell's line and gets a prompt.
+const CLEAR_LINE: &[u8] = b"\x15";
+
+struct Options {
+    device: String,
+    baud: u32,
+    commands: Vec<String>,
+    timeout: Duration,
+    test_timeout: Duration,
+    junit: String,
+    quiet: bool,
+}
+
+fn parse_args() -> Result<Option<Options>, Box<dyn std::error::Error>> {
+    let mut options = Options {
+        device: String::new(),
+        baud: 115200,
+        commands: Vec::new(),
+        timeout: Duration::from_secs(120),
+        test_timeout: Duration::from_secs(10),
+        junit: "junit.xml".to_string(),
+        quiet: false,
+    };
+    let mut device = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(USAGE);
+        match arg.as_str() {
+            "--baud" => options.baud = value()?.parse()?,
+            "--command" => options.commands.push(value()?),
+            "--timeout" => options.timeout = Duration::from_secs_f64(value()?.parse()?),
+            "--test-timeout" => options.test_timeout = Duration::from_secs_f64(value()?.parse()?),
+            "--junit" => options.junit = value()?,
+            "--quiet" => options.quiet = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(None);
+            }
+            _ if device.is_none() => device = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    options.device = device.ok_or(USAGE)?;
+    Ok(Some(options))
+}
+
+/// What the run waits for next.
+#[derive(PartialEq)]
+enum Waiting {
+    /// The summary of the boot run.
+    Summary,
+    /// The shell to finish the last command.
+    Prompt,
+}
+
+struct Session {
+    port: Box<dyn serialport::SerialPort>,
+    parser: results::Parser,
+    quiet: bool,
+    /// The received line so far.
+    line: Vec<u8>,
+    cases: Vec<TestCase>,
+    summary: Option<results::Summary>,
+    experiment: Option<String>,
+    /// When the last test reported or started, if anything is running.
+    progress: Option<Instant>,
+}
+
+impl Session {
+    /// Read what's there, returns `true` if `waiting` is over.
+    fn receive(&mut self, waiting: &Waiting) -> std::io::Result<bool> {
+        let mut buf = [0u8; 1024];
+        let n = match self.port.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if !self.quiet {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&buf[..n])?;
+            stdout.flush()?;
+        }
+
+        let mut done = false;
+        for &b in &buf[..n] {
+            if b != b'\n' {
+                self.line.push(b);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line)
+                .trim_end_matches('\r')
+                .to_string();
+            self.line.clear();
+            for event in self.parser.line(&line) {
+                self.progress = Some(Instant::now());
+                match event {
+                    Event::Experiment(name) => self.experiment = Some(name),
+                    Event::Result(case) => self.cases.push(case),
+                    Event::Summary(summary) => {
+                        self.summary = Some(summary);
+                        done |= *waiting == Waiting::Summary;
+                    }
+                }
+            }
+        }
+        // The prompt doesn't end with a line ending.
+        if *waiting == Waiting::Prompt && self.line.ends_with(PROMPT.as_bytes()) {
+            self.line.clear();
+            done = true;
+        }
+        Ok(done)
+    }
+
+    /// Receive until `waiting` is over, or fail the test in progress if
+    /// it's taking too long.
+    fn wait(&mut self, waiting: Waiting, options: &Options, deadline: Instant) -> bool {
+        // Nothing is running until the board is reset.
+        self.progress = (waiting == Waiting::Prompt).then(Instant::now);
+        loop {
+            match self.receive(&waiting) {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) => return self.abandon(format!("reading {}: {}", options.device, e)),
+            }
+            if self
+                .progress
+                .is_some_and(|progress| progress.elapsed() > options.test_timeout)
+            {
+                let secs = options.test_timeout.as_secs_f64();
+                return self.abandon(format!("no result within {secs} s, hung?"));
+            }
+            if Instant::now() > deadline {
+                let secs = options.timeout.as_secs_f64();
+                return self.abandon(format!("the run took longer than {secs} s"));
+            }
+        }
+    }
+
+    /// Fail whatever was running and stop.
+    fn abandon(&mut self, detail: String) -> bool {
+        eprintln!("test-runner: {detail}");
+        let name = self.experiment.clone().unwrap_or_else(|| "run".to_string());
+        self.cases.push(TestCase {
+            name,
+            channel: None,
+            duration_us: self
+                .progress
+                .map_or(0, |progress| progress.elapsed().as_micros() as u64),
+            status: Status::Fail,
+            mismatches: 0,
+            detail,
+            experiment: self.experiment.clone(),
+        });
+        false
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.port.write_all(bytes)?;
+        self.port.flush()
+    }
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let Some(options) = parse_args()? else {
+        return Ok(ExitCode::SUCCESS);
+    };
+
+    let port = serialport::new(&options.device, options.baud)
+        .timeout(Duration::from_millis(100))
+        .open()?;
+    let mut session = Session {
+        port,
+        parser: results::Parser::new(),
+        quiet: options.quiet,
+        line: Vec::new(),
+        cases: Vec::new(),
+        summary: None,
+        experiment: None,
+        progress: None,
+    };
+
+    let deadline = Instant::now() + options.timeout;
+    if options.commands.is_empty() {
+        eprintln!("test-runner: waiting for the boot run, reset the board");
+        session.wait(Waiting::Summary, &options, deadline);
+    } else {
+        session.send(CLEAR_LINE)?;
+        let mut ok = session.wait(Waiting::Prompt, &options, deadline);
+        for command in &options.commands {
+            if !ok {
+                break;
+            }
+            session.send(command.as_bytes())?;
+            session.send(b"\r")?;
+            ok = session.wait(Waiting::Prompt, &options, deadline);
+        }
+    }
+
+    std::fs::write(
+        &options.junit,
+        results::junit("pico-pio-dma-test", &session.cases),
+    )?;
+
+    let count = |status| session.cases.iter().filter(|c| c.status == status).count();
+    let failed = count(Status::Fail);
+    eprintln!(
+        "test-runner: {} tests, {} passed, {} failed, {} skipped, report in {}",
+        session.cases.len(),
+        count(Status::Pass),
+        failed,
+        count(Status::Skip),
+        options.junit
+    );
+    if let Some(summary) = session.summary {
+        if summary.failed as usize > failed {
+            eprintln!(
+                "test-runner: the firmware counted {} failures, some results were lost",
+                summary.failed
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    Ok(if failed == 0 && !session.cases.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,921 @@
+//! Decoder for the firmware's binary log stream, see `src/blog.rs` in the
+//! firmware for the frame format.
+
+use object::Object;
+use object::ObjectSection;
+use std::fmt::Write;
+
+const SYNC: [u8; 2] = [0xb1, 0x06];
+
+const LEVELS: [&str; 5] = ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+const COLORS: [&str; 5] = ["\x1b[31m", "\x1b[33m", "\x1b[32m", "\x1b[36m", "\x1b[37m"];
+
+/// CRC-16/CCITT-FALSE, as on the target.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The interned format strings from the firmware ELF file.
+pub struct Strings {
+    base: u64,
+    data: Vec<u8>,
+}
+
+pub struct Interned<'a> {
+    pub module: &'a str,
+    pub file: &'a str,
+    pub line: u32,
+    pub format: &'a str,
+}
+
+impl Strings {
+    pub fn load(elf: &[u8]) -> Result<Self, String> {
+        let file = object::File::parse(elf).map_err(|e| e.to_string())?;
+        let section = file.section_by_name(".blog_strings").ok_or(
+            "no .blog_strings section, was the firmware built with `--features binary-log`?",
+        )?;
+        Ok(Strings {
+            base: section.address(),
+            data: section.data().map_err(|e| e.to_string())?.to_vec(),
+        })
+    }
+
+    pub fn get(&self, id: u32) -> Option<Interned<'_>> {
+        let start = (id as u64).checked_sub(self.base)? as usize;
+        let rest = self.data.get(start..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        let s = std::str::from_utf8(&rest[..end]).ok()?;
+
+        let mut fields = s.splitn(4, '\x1f');
+        Some(Interned {
+            module: fields.next()?,
+            file: fields.next()?,
+            line: fields.next()?.parse().ok()?,
+            format: fields.next()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    F32(f32),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+pub enum Body {
+    Interned {
+        id: u32,
+        args: Vec<Value>,
+    },
+    Text {
+        module: String,
+        file: String,
+        line: u32,
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// `log::Level as u8`, 1 = error .. 5 = trace.
+    pub level: u8,
+    /// The core that logged it.
+    pub core: u8,
+    pub timestamp_us: u64,
+    pub body: Body,
+}
+
+/// What the decoder found in the stream.
+#[derive(Clone, Debug)]
+pub enum Item {
+    Record(Record),
+    /// Bytes outside of frames, e.g. text printed before the logger was
+    /// set up or the DMA transport's dropped message notices.
+    Raw(Vec<u8>),
+    /// A frame with a bad CRC or payload.
+    Corrupt(String),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Result<u8, String> {
+        let (&b, rest) = self.data.split_first().ok_or("truncated payload")?;
+        self.data = rest;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err("varint too long".into())
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.varint()? as usize;
+        if len > self.data.len() {
+            return Err("truncated payload".into());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes.to_vec())
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(&self.bytes()?).into_owned())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        Ok(match self.u8()? {
+            0 => Value::Unsigned(self.varint()?),
+            1 => {
+                let v = self.varint()?;
+                Value::Signed((v >> 1) as i64 ^ -((v & 1) as i64))
+            }
+            2 => Value::Bool(self.u8()? != 0),
+            3 => Value::Char(char::from_u32(self.varint()? as u32).unwrap_or('\u{fffd}')),
+            4 => Value::Str(self.str()?),
+            5 => {
+                let b = [self.u8()?, self.u8()?, self.u8()?, self.u8()?];
+                Value::F32(f32::from_le_bytes(b))
+            }
+            6 => Value::Bytes(self.bytes()?),
+            tag => return Err(format!("unknown argument tag {tag}")),
+        })
+    }
+}
+
+fn parse_payload(payload: &[u8]) -> Result<Record, String> {
+    let mut r = Reader { data: payload };
+    let kind = r.u8()?;
+    let level_core = r.u8()?;
+    let (level, core) = (level_core & 0xf, level_core >> 4);
+    if !(1..=5).contains(&level) {
+        return Err(format!("bad level {level}"));
+    }
+    let timestamp_us = r.varint()?;
+
+    let body = match kind {
+        0 => {
+            let id = u32::from_le_bytes([r.u8()?, r.u8()?, r.u8()?, r.u8()?]);
+            let mut args = Vec::new();
+            while !r.data.is_empty() {
+                args.push(r.value()?);
+            }
+            Body::Interned { id, args }
+        }
+        1 => Body::Text {
+            module: r.str()?,
+            file: r.str()?,
+            line: r.varint()? as u32,
+            message: r.str()?,
+        },
+        kind => return Err(format!("unknown record kind {kind}")),
+    };
+
+    Ok(Record {
+        level,
+        core,
+        timestamp_us,
+        body,
+    })
+}
+
+/// Splits a byte stream into frames and the bytes between them.
+#[derive(Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The next complete item. With `eof`, whatever is left that can't be
+    /// a frame is returned as raw bytes instead of waiting for more.
+    pub fn next(&mut self, eof: bool) -> Option<Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        let Some(start) = self.buf.windows(2).position(|w| w == SYNC) else {
+            // Keep a trailing first sync byte, the second may be on its way.
+            let keep = (!eof && self.buf.last() == Some(&SYNC[0])) as usize;
+            let raw: Vec<u8> = self.buf.drain(..self.buf.len() - keep).collect();
+            return (!raw.is_empty()).then_some(Item::Raw(raw));
+        };
+        if start > 0 {
+            return Some(Item::Raw(self.buf.drain(..start).collect()));
+        }
+
+        let Some(&len) = self.buf.get(2) else {
+            return eof.then(|| Item::Raw(std::mem::take(&mut self.buf)));
+        };
+        let end = 3 + len as usize;
+        if self.buf.len() < end + 2 {
+            return eof.then(|| Item::Raw(std::mem::take(&mut self.buf)));
+        }
+
+        let crc = u16::from_le_bytes([self.buf[end], self.buf[end + 1]]);
+        if crc16(&self.buf[2..end]) != crc {
+            // Could be sync bytes in the middle of raw text, resync after them.
+            self.buf.drain(..2);
+            return Some(Item::Corrupt("bad CRC".into()));
+        }
+
+        let item = match parse_payload(&self.buf[3..end]) {
+            Ok(record) => Item::Record(record),
+            Err(e) => Item::Corrupt(e),
+        };
+        self.buf.drain(..end + 2);
+        Some(item)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    None,
+    Hex,
+    Seconds,
+    Delta,
+}
+
+/// How to render records, the same fields as the firmware's `log_format`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineFormat {
+    pub timestamp: Timestamp,
+    pub core: bool,
+    pub level: bool,
+    pub module: bool,
+    pub source_path: bool,
+    pub color: bool,
+}
+
+/// The firmware's default without the colour.
+impl Default for LineFormat {
+    fn default() -> Self {
+        LineFormat {
+            timestamp: Timestamp::Hex,
+            core: true,
+            level: true,
+            module: true,
+            source_path: false,
+            color: false,
+        }
+    }
+}
+
+impl LineFormat {
+    /// Parse a description like `secs,core,level,module,file,color`, see
+    /// `src/log_format.rs` in the firmware.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut format = LineFormat {
+            timestamp: Timestamp::None,
+            core: false,
+            level: false,
+            module: false,
+            source_path: false,
+            color: false,
+        };
+        for field in spec.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let timestamp = match field {
+                "hex" => Timestamp::Hex,
+                "secs" => Timestamp::Seconds,
+                "delta" => Timestamp::Delta,
+                "core" => {
+                    format.core = true;
+                    continue;
+                }
+                "level" => {
+                    format.level = true;
+                    continue;
+                }
+                "module" => {
+                    format.module = true;
+                    continue;
+                }
+                "file" => {
+                    format.source_path = true;
+                    continue;
+                }
+                "color" => {
+                    format.color = true;
+                    continue;
+                }
+                _ => return Err(format!("unknown log format field {field:?}")),
+            };
+            if format.timestamp != Timestamp::None {
+                return Err(format!("more than one timestamp in {spec:?}"));
+            }
+            format.timestamp = timestamp;
+        }
+        Ok(format)
+    }
+}
+
+/// Renders records like the firmware's text logger would print them.
+pub struct Renderer {
+    pub format: LineFormat,
+    /// The previous record's timestamp, for `Timestamp::Delta`.
+    previous_us: u64,
+}
+
+impl Renderer {
+    pub fn new(format: LineFormat) -> Self {
+        Renderer {
+            format,
+            previous_us: 0,
+        }
+    }
+
+    /// The line for `record`, without the line ending.
+    pub fn render(&mut self, record: &Record, strings: &Strings) -> String {
+        let (module, file, line, message) = match &record.body {
+            Body::Interned { id, args } => match strings.get(*id) {
+                Some(s) => (s.module, s.file, s.line, format(s.format, args)),
+                None => ("?", "?", 0, format!("<unknown string {id:#x}: {args:?}>")),
+            },
+            Body::Text {
+                module,
+                file,
+                line,
+                message,
+            } => (module.as_str(), file.as_str(), *line, message.clone()),
+        };
+
+        let format = self.format;
+        let time_us = record.timestamp_us;
+        let delta_us = time_us.saturating_sub(self.previous_us);
+        self.previous_us = time_us;
+
+        let level = record.level as usize - 1;
+        let mut out = String::new();
+        if format.color {
+            out.push_str(COLORS[level]);
+        }
+        match format.timestamp {
+            Timestamp::None => {}
+            Timestamp::Hex => write!(out, "{time_us:08x}:").unwrap(),
+            Timestamp::Seconds => {
+                write!(out, "{}.{:06}:", time_us / 1_000_000, time_us % 1_000_000).unwrap()
+            }
+            Timestamp::Delta => write!(
+                out,
+                "+{}.{:06}:",
+                delta_us / 1_000_000,
+                delta_us % 1_000_000
+            )
+            .unwrap(),
+        }
+        if format.core {
+            write!(out, "{}:", record.core).unwrap();
+        }
+        if format.level {
+            write!(out, "[{:7}]", LEVELS[level]).unwrap();
+        }
+        if format.module || format.source_path {
+            out.push('[');
+            if format.module {
+                out.push_str(module);
+            }
+            if format.module && format.source_path {
+                out.push(' ');
+            }
+            if format.source_path {
+                write!(out, "{file}@{line}").unwrap();
+            }
+            out.push(']');
+        }
+        let any = format.timestamp != Timestamp::None
+            || format.core
+            || format.level
+            || format.module
+            || format.source_path;
+        if any {
+            out.push(' ');
+        }
+        out.push_str(&message);
+        if format.color {
+            out.push_str("\x1b[0m");
+        }
+        out
+    }
+}
+
+/// A `{...}` format spec, the subset `blog!` arguments can use.
+#[derive(Clone, Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    plus: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    /// The type without the `?`: `x`, `X`, `b`, `o` or empty.
+    radix: String,
+    debug: bool,
+}
+
+fn parse_spec(s: &str) -> Spec {
+    let chars: Vec<char> = s.chars().collect();
+    let mut spec = Spec::default();
+    let mut i = 0;
+
+    if chars.len() >= 2 && "<>^".contains(chars[1]) {
+        spec.fill = Some(chars[0]);
+        spec.align = Some(chars[1]);
+        i = 2;
+    } else if chars.first().is_some_and(|c| "<>^".contains(*c)) {
+        spec.align = Some(chars[0]);
+        i = 1;
+    }
+    if chars.get(i) == Some(&'+') {
+        spec.plus = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        spec.alternate = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') {
+        spec.zero = true;
+        i += 1;
+    }
+    while let Some(d) = chars.get(i).and_then(|c| c.to_digit(10)) {
+        spec.width = spec.width * 10 + d as usize;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let mut precision = 0;
+        while let Some(d) = chars.get(i).and_then(|c| c.to_digit(10)) {
+            precision = precision * 10 + d as usize;
+            i += 1;
+        }
+        spec.precision = Some(precision);
+    }
+
+    let ty: String = chars[i..].iter().collect();
+    spec.debug = ty.ends_with('?');
+    spec.radix = ty.trim_end_matches('?').to_string();
+    spec
+}
+
+fn pad(s: String, spec: &Spec, default_align: char) -> String {
+    let len = s.chars().count();
+    if len >= spec.width {
+        return s;
+    }
+    let fill = spec.fill.unwrap_or(' ');
+    let n = spec.width - len;
+    let (left, right) = match spec.align.unwrap_or(default_align) {
+        '<' => (0, n),
+        '^' => (n / 2, n - n / 2),
+        _ => (n, 0),
+    };
+    let fill = |n| std::iter::repeat_n(fill, n).collect::<String>();
+    fill(left) + &s + &fill(right)
+}
+
+/// Pad a number, `0` padding goes between the sign or prefix and the digits.
+fn pad_number(sign: &str, digits: String, spec: &Spec) -> String {
+    if spec.zero && spec.align.is_none() {
+        let n = spec.width.saturating_sub(sign.len() + digits.len());
+        return format!("{sign}{}{digits}", "0".repeat(n));
+    }
+    pad(format!("{sign}{digits}"), spec, '>')
+}
+
+fn format_int(negative: bool, magnitude: u64, spec: &Spec) -> String {
+    let (prefix, digits) = match spec.radix.as_str() {
+        "x" => ("0x", format!("{magnitude:x}")),
+        "X" => ("0x", format!("{magnitude:X}")),
+        "b" => ("0b", format!("{magnitude:b}")),
+        "o" => ("0o", format!("{magnitude:o}")),
+        _ => ("", magnitude.to_string()),
+    };
+    let mut sign = String::from(if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else {
+        ""
+    });
+    if spec.alternate {
+        sign.push_str(prefix);
+    }
+    pad_number(&sign, digits, spec)
+}
+
+fn format_value(value: &Value, spec: &Spec) -> String {
+    match value {
+        Value::Unsigned(v) => format_int(false, *v, spec),
+        // Like the target, hex and friends show the two's complement.
+        Value::Signed(v) if !spec.radix.is_empty() => format_int(false, *v as u64, spec),
+        Value::Signed(v) => format_int(*v < 0, v.unsigned_abs(), spec),
+        Value::F32(v) => {
+            let mut s = match spec.precision {
+                Some(p) => format!("{:.*}", p, v.abs()),
+                None => format!("{}", v.abs()),
+            };
+            if spec.debug && v.is_finite() && !s.contains(['.', 'e']) {
+                s.push_str(".0");
+            }
+            let sign = if v.is_sign_negative() {
+                "-"
+            } else if spec.plus {
+                "+"
+            } else {
+                ""
+            };
+            pad_number(sign, s, spec)
+        }
+        Value::Bool(v) => pad(v.to_string(), spec, '<'),
+        Value::Char(v) if spec.debug => pad(format!("{v:?}"), spec, '<'),
+        Value::Char(v) => pad(v.to_string(), spec, '<'),
+        Value::Str(v) if spec.debug => pad(format!("{v:?}"), spec, '<'),
+        Value::Str(v) => {
+            let s = match spec.precision {
+                Some(p) => v.chars().take(p).collect(),
+                None => v.clone(),
+            };
+            pad(s, spec, '<')
+        }
+        // Only `{:?}` is possible for slices, and it formats every element
+        // with the spec.
+        Value::Bytes(v) => {
+            let items: Vec<String> = v
+                .iter()
+                .map(|b| format_int(false, *b as u64, spec))
+                .collect();
+            format!("[{}]", items.join(", "))
+        }
+    }
+}
+
+/// Format `args` like `format!` would with `format` on the target.
+pub fn format(format: &str, args: &[Value]) -> String {
+    let mut out = String::new();
+    let mut next_arg = 0;
+    let mut chars = format.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek().map(|&(_, c)| c) == Some('}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let Some(len) = format[i..].find('}') else {
+                    out.push_str(&format[i..]);
+                    break;
+                };
+                let placeholder = &format[i + 1..i + len];
+                while chars.peek().is_some_and(|&(j, _)| j <= i + len) {
+                    chars.next();
+                }
+
+                let (position, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+                let index = match position.parse() {
+                    Ok(index) => index,
+                    Err(_) => {
+                        next_arg += 1;
+                        next_arg - 1
+                    }
+                };
+                match args.get(index) {
+                    Some(value) => out.push_str(&format_value(value, &parse_spec(spec))),
+                    None => write!(out, "{{{placeholder}}}").unwrap(),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds frames the way `Frame` in the firmware's `src/blog.rs` does.
+    struct Encoder(Vec<u8>);
+
+    impl Encoder {
+        fn new(kind: u8, level: u8, core: u8, timestamp_us: u64) -> Self {
+            let mut e = Encoder(vec![kind, level | core << 4]);
+            e.varint(timestamp_us);
+            e
+        }
+
+        fn varint(&mut self, mut v: u64) -> &mut Self {
+            while v >= 0x80 {
+                self.0.push(v as u8 | 0x80);
+                v >>= 7;
+            }
+            self.0.push(v as u8);
+            self
+        }
+
+        fn bytes(&mut self, b: &[u8]) -> &mut Self {
+            self.varint(b.len() as u64);
+            self.0.extend_from_slice(b);
+            self
+        }
+
+        fn tagged(&mut self, tag: u8) -> &mut Self {
+            self.0.push(tag);
+            self
+        }
+
+        fn frame(&self) -> Vec<u8> {
+            let mut frame = SYNC.to_vec();
+            frame.push(self.0.len() as u8);
+            frame.extend_from_slice(&self.0);
+            let crc = crc16(&frame[2..]);
+            frame.extend_from_slice(&crc.to_le_bytes());
+            frame
+        }
+    }
+
+    fn text_frame(message: &str) -> Vec<u8> {
+        Encoder::new(1, 3, 0, 1_234_567)
+            .bytes(b"pio::dma")
+            .bytes(b"src/dma.rs")
+            .varint(42)
+            .bytes(message.as_bytes())
+            .frame()
+    }
+
+    fn decode_all(stream: &[u8]) -> Vec<Item> {
+        let mut decoder = Decoder::new();
+        decoder.push(stream);
+        std::iter::from_fn(|| decoder.next(true)).collect()
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn interned_round_trip() {
+        let mut e = Encoder::new(0, 4, 1, 300);
+        e.0.extend(0x1234_5678u32.to_le_bytes());
+        e.tagged(0).varint(u64::MAX);
+        e.tagged(1).varint(9); // zigzag -5
+        e.tagged(2).tagged(1);
+        e.tagged(3).varint('é' as u64);
+        e.tagged(4).bytes("hé".as_bytes());
+        e.tagged(5).0.extend(1.5f32.to_le_bytes());
+        let frame = e.frame();
+
+        let items = decode_all(&frame);
+        let [Item::Record(record)] = items.as_slice() else {
+            panic!("{:?}", items);
+        };
+        assert_eq!(
+            (record.level, record.core, record.timestamp_us),
+            (4, 1, 300)
+        );
+        let Body::Interned { id, args } = &record.body else {
+            panic!("{:?}", record.body);
+        };
+        assert_eq!(*id, 0x1234_5678);
+        assert_eq!(
+            format!("{:?}", args),
+            r#"[Unsigned(18446744073709551615), Signed(-5), Bool(true), Char('é'), Str("hé"), F32(1.5)]"#
+        );
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let items = decode_all(&text_frame("hello"));
+        let [Item::Record(record)] = items.as_slice() else {
+            panic!("{:?}", items);
+        };
+        let Body::Text {
+            module,
+            file,
+            line,
+            message,
+        } = &record.body
+        else {
+            panic!("{:?}", record.body);
+        };
+        assert_eq!(
+            (module.as_str(), file.as_str(), *line, message.as_str()),
+            ("pio::dma", "src/dma.rs", 42, "hello")
+        );
+    }
+
+    #[test]
+    fn bytes_between_frames() {
+        let mut stream = b"boot\r\n".to_vec();
+        stream.extend(text_frame("one"));
+        stream.extend(b"3 messages dropped\r\n");
+        stream.extend(text_frame("two"));
+        let items = decode_all(&stream);
+        assert_eq!(items.len(), 4);
+        assert!(matches!(&items[0], Item::Raw(raw) if raw == b"boot\r\n"));
+        assert!(matches!(&items[1], Item::Record(_)));
+        assert!(matches!(&items[2], Item::Raw(raw) if raw == b"3 messages dropped\r\n"));
+        assert!(matches!(&items[3], Item::Record(_)));
+    }
+
+    #[test]
+    fn bad_crc_is_rejected() {
+        let mut frame = text_frame("hello");
+        frame[10] ^= 0x20;
+        let items = decode_all(&frame);
+        assert!(matches!(&items[0], Item::Corrupt(e) if e == "bad CRC"));
+        assert!(!items.iter().any(|i| matches!(i, Item::Record(_))));
+    }
+
+    #[test]
+    fn resync_after_corruption() {
+        // A stray sync with a length running into the next frame.
+        let mut stream = vec![0xb1, 0x06, 0x08, b'x'];
+        stream.extend(text_frame("after"));
+        let items = decode_all(&stream);
+        let records: Vec<_> = items
+            .iter()
+            .filter_map(|i| match i {
+                Item::Record(r) => Some(r),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(items[0], Item::Corrupt(_)));
+        assert_eq!(records.len(), 1);
+        assert!(matches!(&records[0].body, Body::Text { message, .. } if message == "after"));
+    }
+
+    #[test]
+    fn frames_split_across_reads() {
+        let frame = text_frame("split");
+        let mut decoder = Decoder::new();
+        for chunk in frame.chunks(3) {
+            assert!(decoder.next(false).is_none());
+            decoder.push(chunk);
+        }
+        assert!(matches!(decoder.next(false), Some(Item::Record(_))));
+        assert!(decoder.next(true).is_none());
+    }
+
+    #[test]
+    fn bad_payload() {
+        let items = decode_all(&Encoder::new(0, 9, 0, 0).frame());
+        assert!(matches!(&items[0], Item::Corrupt(e) if e == "bad level 9"));
+        let items = decode_all(&Encoder::new(7, 1, 0, 0).frame());
+        assert!(matches!(&items[0], Item::Corrupt(e) if e == "unknown record kind 7"));
+        let items = decode_all(&Encoder::new(0, 1, 0, 0).tagged(1).frame());
+        assert!(matches!(&items[0], Item::Corrupt(e) if e == "truncated payload"));
+    }
+
+    #[test]
+    fn format_specs() {
+        use Value::*;
+        let cases: &[(&str, Value, &str)] = &[
+            ("{}", Unsigned(42), "42"),
+            ("{:x}", Unsigned(0xbeef), "beef"),
+            ("{:X}", Unsigned(0xbeef), "BEEF"),
+            ("{:#x}", Unsigned(0xbeef), "0xbeef"),
+            ("{:08x}", Unsigned(0xbeef), "0000beef"),
+            ("{:#010x}", Unsigned(0xbeef), "0x0000beef"),
+            ("{:b}", Unsigned(5), "101"),
+            ("{:#b}", Unsigned(5), "0b101"),
+            ("{:5}", Unsigned(7), "    7"),
+            ("{:<5}|", Unsigned(7), "7    |"),
+            ("{:^5}", Unsigned(7), "  7  "),
+            ("{:*^7}", Unsigned(7), "***7***"),
+            ("{}", Signed(-5), "-5"),
+            ("{:+}", Signed(5), "+5"),
+            ("{:05}", Signed(-5), "-0005"),
+            ("{:.2}", F32(1.5), "1.50"),
+            ("{:?}", F32(2.0), "2.0"),
+            ("{}", F32(-2.0), "-2"),
+            ("{}", Bool(true), "true"),
+            ("{:6}|", Bool(false), "false |"),
+            ("{:?}", Char('a'), "'a'"),
+            ("{}", Str("hi".into()), "hi"),
+            ("{:?}", Str("hi".into()), "\"hi\""),
+            ("{:.1}", Str("hi".into()), "h"),
+            ("{:>4}", Str("hi".into()), "  hi"),
+            ("{:02x?}", Bytes(vec![1, 0xab]), "[01, ab]"),
+            ("{:?}", Bytes(vec![1, 2]), "[1, 2]"),
+        ];
+        for (spec, value, expected) in cases {
+            assert_eq!(
+                format(spec, std::slice::from_ref(value)),
+                *expected,
+                "{spec}"
+            );
+        }
+
+        let args = [Unsigned(1), Unsigned(2)];
+        assert_eq!(format("{{{}}} {1} {0} {}", &args), "{1} 2 1 2");
+        assert_eq!(format("{} {} {:x}", &args), "1 2 {:x}");
+    }
+
+    #[test]
+    fn line_formats() {
+        assert_eq!(
+            LineFormat::parse("hex,core,level,module").unwrap(),
+            LineFormat::default()
+        );
+        assert!(LineFormat::parse("secs,hex").is_err());
+        assert!(LineFormat::parse("colour").is_err());
+
+        let strings = Strings {
+            base: 0x1000,
+            data: b"xx\0blink\x1fsrc/blink.rs\x1f7\x1fon {} for {:#x}\0".to_vec(),
+        };
+        let record = |timestamp_us, body| Record {
+            level: 2,
+            core: 1,
+            timestamp_us,
+            body,
+        };
+        let interned = record(
+            2_500_000,
+            Body::Interned {
+                id: 0x1003,
+                args: vec![Value::Bool(true), Value::Unsigned(16)],
+            },
+        );
+
+        let mut renderer = Renderer::new(LineFormat::default());
+        assert_eq!(
+            renderer.render(&interned, &strings),
+            "002625a0:1:[WARN   ][blink] on true for 0x10"
+        );
+
+        let mut renderer = Renderer::new(LineFormat::parse("delta,file,color").unwrap());
+        let text = record(
+            2_000_000,
+            Body::Text {
+                module: "m".into(),
+                file: "f.rs".into(),
+                line: 3,
+                message: "text".into(),
+            },
+        );
+        assert_eq!(
+            renderer.render(&text, &strings),
+            "\x1b[33m+2.000000:[f.rs@3] text\x1b[0m"
+        );
+        assert_eq!(
+            renderer.render(&interned, &strings),
+            "\x1b[33m+0.500000:[src/blink.rs@7] on true for 0x10\x1b[0m"
+        );
+
+        let mut renderer = Renderer::new(LineFormat::parse("").unwrap());
+        let unknown = record(
+            0,
+            Body::Interned {
+                id: 0x9999,
+                args: vec![],
+            },
+        );
+        assert_eq!(
+            renderer.render(&unknown, &strings),
+            "<unknown string 0x9999: []>"
+        );
+    }
+}
@@ -0,0 +1,12 @@
+//! Host-side tools for the firmware, see `src/bin`.
+
+pub mod blog;
+#[path = "../../src/golden.rs"]
+pub mod golden;
+#[path = "../../src/log_sink.rs"]
+pub mod log_sink;
+#[path = "../../src/pio_programs.rs"]
+pub mod pio_programs;
+#[path = "../../src/pio_timing.rs"]
+pub mod pio_timing;
+pub mod results;
@@ -0,0 +1,519 @@
+//! The test results the firmware writes next to its log, see `src/report.rs`
+//! in the firmware, and the JUnit XML report made of them.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub name: String,
+    pub channel: Option<u8>,
+    pub duration_us: u64,
+    pub status: Status,
+    pub mismatches: u64,
+    pub detail: String,
+    /// The experiment that was running, from its `=== name` log line.
+    pub experiment: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Summary {
+    pub total: u32,
+    pub passed: u32,
+    pub failed: u32,
+    pub skipped: u32,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Experiment(String),
+    Result(TestCase),
+    Summary(Summary),
+}
+
+/// Picks the results out of the lines of a serial capture, in either
+/// format.
+#[derive(Default)]
+pub struct Parser {
+    experiment: Option<String>,
+    /// A TAP failure waiting for the end of its YAML block.
+    failure: Option<TestCase>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser::default()
+    }
+
+    /// Feed one line without its line ending. Colour codes, e.g. the reset
+    /// ending the previous log line, are ignored.
+    pub fn line(&mut self, line: &str) -> Vec<Event> {
+        let line = &without_escapes(line);
+        let mut events = Vec::new();
+
+        if let Some(mut failure) = self.failure.take() {
+            if let Some(field) = line.strip_prefix("  ") {
+                if field != "..." {
+                    if let Some((key, value)) = field.split_once(": ") {
+                        match key {
+                            "duration_us" => failure.duration_us = value.parse().unwrap_or(0),
+                            "mismatches" => failure.mismatches = value.parse().unwrap_or(0),
+                            "detail" => failure.detail = unquote(value),
+                            _ => {}
+                        }
+                    }
+                    self.failure = Some(failure);
+                    return events;
+                }
+                events.push(Event::Result(failure));
+                return events;
+            }
+            // No YAML block after all.
+            events.push(Event::Result(failure));
+        }
+
+        if let Some(name) = experiment_start(line) {
+            self.experiment = Some(name.to_string());
+            events.push(Event::Experiment(name.to_string()));
+        } else if let Some(case) = self.tap_result(line) {
+            if case.status == Status::Fail {
+                self.failure = Some(case);
+            } else {
+                events.push(Event::Result(case));
+            }
+        } else if let Some(summary) = tap_summary(line) {
+            events.push(Event::Summary(summary));
+        } else if line.starts_with('{') {
+            events.extend(self.json(line));
+        }
+        events
+    }
+
+    /// `ok 3 - dma_simple ch5 # 12 us`, `# SKIP why` for skipped ones.
+    fn tap_result(&self, line: &str) -> Option<TestCase> {
+        let (status, rest) = match line.strip_prefix("not ok ") {
+            Some(rest) => (Status::Fail, rest),
+            None => (Status::Pass, line.strip_prefix("ok ")?),
+        };
+        let (_number, rest) = rest.split_once(" - ")?;
+        let (description, directive) = rest.split_once(" # ").unwrap_or((rest, ""));
+
+        let (name, channel) = match description.rsplit_once(" ch") {
+            Some((name, channel)) if channel.parse::<u8>().is_ok() => (name, channel.parse().ok()),
+            _ => (description, None),
+        };
+        let mut case = TestCase {
+            name: name.to_string(),
+            channel,
+            duration_us: 0,
+            status,
+            mismatches: 0,
+            detail: String::new(),
+            experiment: self.experiment.clone(),
+        };
+        match directive.strip_prefix("SKIP") {
+            Some(detail) => {
+                case.status = Status::Skip;
+                case.detail = detail.trim().to_string();
+            }
+            None => case.duration_us = directive.trim_end_matches(" us").parse().unwrap_or(0),
+        }
+        Some(case)
+    }
+
+    fn json(&self, line: &str) -> Option<Event> {
+        let object = parse_json_object(line)?;
+        let number = |key: &str| match object.get(key) {
+            Some(Json::Number(n)) => *n,
+            _ => 0,
+        };
+        let string = |key: &str| match object.get(key) {
+            Some(Json::String(s)) => s.clone(),
+            _ => String::new(),
+        };
+
+        match string("type").as_str() {
+            "result" => Some(Event::Result(TestCase {
+                name: string("name"),
+                channel: match object.get("channel") {
+                    Some(Json::Number(n)) => u8::try_from(*n).ok(),
+                    _ => None,
+                },
+                duration_us: number("duration_us"),
+                status: match string("status").as_str() {
+                    "pass" => Status::Pass,
+                    "skip" => Status::Skip,
+                    _ => Status::Fail,
+                },
+                mismatches: number("mismatches"),
+                detail: string("detail"),
+                experiment: self.experiment.clone(),
+            })),
+            "summary" => Some(Event::Summary(Summary {
+                total: number("total") as u32,
+                passed: number("passed") as u32,
+                failed: number("failed") as u32,
+                skipped: number("skipped") as u32,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// `line` without ANSI CSI sequences like `ESC[0m`.
+fn without_escapes(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        if chars.clone().next() == Some('[') {
+            chars.next();
+            // Parameters and intermediates up to the final byte.
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The name from the `=== name (resources)` line logged before each
+/// experiment, whatever the log prefix.
+fn experiment_start(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("=== ")?;
+    let (name, _) = rest.split_once(" (")?;
+    Some(name)
+}
+
+/// `# pass 10 fail 1 skip 2`, after the plan.
+fn tap_summary(line: &str) -> Option<Summary> {
+    let mut words = line.strip_prefix("# pass ")?.split(' ');
+    let passed = words.next()?.parse().ok()?;
+    let failed = words.nth(1)?.parse().ok()?;
+    let skipped = words.nth(1)?.parse().ok()?;
+    Some(Summary {
+        total: passed + failed + skipped,
+        passed,
+        failed,
+        skipped,
+    })
+}
+
+/// A JSON string as the firmware escapes it.
+fn unquote(s: &str) -> String {
+    let s = s.strip_prefix('"').unwrap_or(s);
+    let s = s.strip_suffix('"').unwrap_or(s);
+    let mut chars = s.chars();
+    let mut out = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                out.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+            }
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+enum Json {
+    String(String),
+    Number(u64),
+    Other,
+}
+
+/// Just enough JSON for the flat objects of the report.
+fn parse_json_object(line: &str) -> Option<HashMap<String, Json>> {
+    let mut rest = line.trim().strip_prefix('{')?.strip_suffix('}')?.trim();
+    let mut object = HashMap::new();
+
+    // A string at the start of `rest` and what follows it.
+    fn string(rest: &str) -> Option<(String, &str)> {
+        let body = rest.strip_prefix('"')?;
+        let mut escaped = false;
+        let end = body.char_indices().find_map(|(i, c)| {
+            let end = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end.then_some(i)
+        })?;
+        Some((unquote(&body[..end]), &body[end + 1..]))
+    }
+
+    while !rest.is_empty() {
+        let (key, after) = string(rest)?;
+        rest = after.trim_start().strip_prefix(':')?.trim_start();
+        let value = if rest.starts_with('"') {
+            let (value, after) = string(rest)?;
+            rest = after;
+            Json::String(value)
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim();
+            rest = &rest[end..];
+            value.parse().map(Json::Number).unwrap_or(Json::Other)
+        };
+        object.insert(key, value);
+        rest = rest.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Some(object)
+}
+
+fn xml_escaped(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c if (c as u32) < 0x20 && !matches!(c, '\n' | '\t') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A JUnit XML report with one suite of `cases`, each in the class of its
+/// experiment.
+pub fn junit(suite: &str, cases: &[TestCase]) -> String {
+    let count = |status| cases.iter().filter(|c| c.status == status).count();
+    let seconds = |us: u64| us as f64 / 1e6;
+    let total_us: u64 = cases.iter().map(|c| c.duration_us).sum();
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).ok();
+    writeln!(
+        xml,
+        r#"<testsuites tests="{}" failures="{}" skipped="{}" time="{:.6}">"#,
+        cases.len(),
+        count(Status::Fail),
+        count(Status::Skip),
+        seconds(total_us)
+    )
+    .ok();
+    writeln!(
+        xml,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.6}">"#,
+        xml_escaped(suite),
+        cases.len(),
+        count(Status::Fail),
+        count(Status::Skip),
+        seconds(total_us)
+    )
+    .ok();
+    for case in cases {
+        let mut name = case.name.clone();
+        if let Some(channel) = case.channel {
+            write!(name, " ch{}", channel).ok();
+        }
+        write!(
+            xml,
+            r#"    <testcase name="{}" classname="{}" time="{:.6}""#,
+            xml_escaped(&name),
+            xml_escaped(case.experiment.as_deref().unwrap_or(suite)),
+            seconds(case.duration_us)
+        )
+        .ok();
+        match case.status {
+            Status::Pass => writeln!(xml, "/>"),
+            Status::Skip => writeln!(
+                xml,
+                ">\n      <skipped message=\"{}\"/>\n    </testcase>",
+                xml_escaped(&case.detail)
+            ),
+            Status::Fail => writeln!(
+                xml,
+                ">\n      <failure message=\"{}\">mismatches: {}</failure>\n    </testcase>",
+                xml_escaped(&case.detail),
+                case.mismatches
+            ),
+        }
+        .ok();
+    }
+    writeln!(xml, "  </testsuite>\n</testsuites>").ok();
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Vec<Event> {
+        let mut parser = Parser::new();
+        lines.iter().flat_map(|line| parser.line(line)).collect()
+    }
+
+    fn results(events: &[Event]) -> Vec<&TestCase> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Result(case) => Some(case),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tap_pass_and_skip() {
+        let events = parse(&[
+            "I 00012 registry: === dma (no PIO, dma 0,1)",
+            "ok 1 - dma_simple ch5 # 12 us",
+            "ok 2 - dma_tests ch10 # SKIP taken by the UART",
+        ]);
+        let cases = results(&events);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "dma_simple");
+        assert_eq!(cases[0].channel, Some(5));
+        assert_eq!(cases[0].duration_us, 12);
+        assert_eq!(cases[0].status, Status::Pass);
+        assert_eq!(cases[0].experiment.as_deref(), Some("dma"));
+        assert_eq!(cases[1].status, Status::Skip);
+        assert_eq!(cases[1].detail, "taken by the UART");
+    }
+
+    #[test]
+    fn tap_failure_with_yaml() {
+        let events = parse(&[
+            "not ok 3 - invert_twice # 40 us",
+            "  ---",
+            "  duration_us: 41",
+            "  mismatches: 7",
+            "  detail: \"output differs \\\"here\\\"\"",
+            "  ...",
+            "ok 4 - dma_copy # 3 us",
+        ]);
+        let cases = results(&events);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "invert_twice");
+        assert_eq!(cases[0].channel, None);
+        assert_eq!(cases[0].status, Status::Fail);
+        assert_eq!(cases[0].duration_us, 41);
+        assert_eq!(cases[0].mismatches, 7);
+        assert_eq!(cases[0].detail, "output differs \"here\"");
+        assert_eq!(cases[1].name, "dma_copy");
+    }
+
+    #[test]
+    fn tap_failure_without_yaml() {
+        let events = parse(&["not ok 1 - scaler # 5 us", "ok 2 - dma_copy # 3 us"]);
+        let cases = results(&events);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].status, Status::Fail);
+        assert_eq!(cases[1].status, Status::Pass);
+    }
+
+    #[test]
+    fn tap_summary() {
+        let events = parse(&["1..13", "# pass 10 fail 1 skip 2"]);
+        let [Event::Summary(summary)] = events.as_slice() else {
+            panic!("{:?}", events);
+        };
+        assert_eq!(summary.total, 13);
+        assert_eq!(summary.passed, 10);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.skipped, 2);
+    }
+
+    #[test]
+    fn json_lines() {
+        let events = parse(&[
+            r#"{"type":"start","version":1}"#,
+            r#"{"type":"result","name":"dma_random","channel":3,"duration_us":900,"status":"fail","mismatches":2,"detail":"case 4, \"seed\" 0x7"}"#,
+            r#"{"type":"result","name":"scaler","channel":null,"duration_us":10,"status":"pass","mismatches":0,"detail":""}"#,
+            r#"{"type":"summary","total":2,"passed":1,"failed":1,"skipped":0}"#,
+        ]);
+        let cases = results(&events);
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "dma_random");
+        assert_eq!(cases[0].channel, Some(3));
+        assert_eq!(cases[0].status, Status::Fail);
+        assert_eq!(cases[0].mismatches, 2);
+        assert_eq!(cases[0].detail, "case 4, \"seed\" 0x7");
+        assert_eq!(cases[1].channel, None);
+        assert_eq!(cases[1].status, Status::Pass);
+        assert!(matches!(
+            events.last(),
+            Some(Event::Summary(Summary { total: 2, .. }))
+        ));
+    }
+
+    #[test]
+    fn colour_codes() {
+        // Each coloured log line ends with a reset, in front of the next line.
+        let events = parse(&[
+            "\x1b[32mI 00012 registry: === dma (no PIO, dma 0)",
+            "\x1b[0mok 1 - dma_simple ch0 # 12 us",
+            "\x1b[0mnot ok 2 - dma_copy # 3 us",
+            "\x1b[0m  ---",
+            "\x1b[0m  mismatches: 1",
+            "\x1b[0m  ...",
+            concat!(
+                "\x1b[0m",
+                r#"{"type":"result","name":"scaler","duration_us":1,"status":"pass"}"#
+            ),
+            "\x1b[0m# pass 2 fail 1 skip 0",
+        ]);
+        let cases = results(&events);
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[0].experiment.as_deref(), Some("dma"));
+        assert_eq!(cases[1].mismatches, 1);
+        assert_eq!(cases[2].name, "scaler");
+        assert!(matches!(
+            events.last(),
+            Some(Event::Summary(Summary { total: 3, .. }))
+        ));
+    }
+
+    #[test]
+    fn junit_report() {
+        let case = |name: &str, channel, status, detail: &str| TestCase {
+            name: name.to_string(),
+            channel,
+            duration_us: 1500,
+            status,
+            mismatches: 4,
+            detail: detail.to_string(),
+            experiment: Some("dma".to_string()),
+        };
+        let xml = junit(
+            "pico",
+            &[
+                case("dma_simple", Some(5), Status::Pass, ""),
+                case("dma_tests", Some(10), Status::Skip, "taken by the UART"),
+                case("a<b", None, Status::Fail, "\"x\" & y"),
+            ],
+        );
+        assert!(xml.contains(
+            r#"<testsuite name="pico" tests="3" failures="1" skipped="1" time="0.004500">"#
+        ));
+        assert!(
+            xml.contains(r#"<testcase name="dma_simple ch5" classname="dma" time="0.001500"/>"#)
+        );
+        assert!(xml.contains(r#"<skipped message="taken by the UART"/>"#));
+        assert!(xml.contains(r#"<testcase name="a&lt;b" classname="dma""#));
+        assert!(xml.contains(r#"<failure message="&quot;x&quot; &amp; y">mismatches: 4</failure>"#));
+        assert!(xml.trim_end().ends_with("</testsuites>"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Static RAM for experiment buffers whose size is only known at run time.
+//!
+//! `with` lends the whole arena to a closure, which cuts word-aligned
+//! buffers off it with `Arena::alloc`; they're all returned when the
+//! closure does:
+//!
+//! ```ignore
+//! arena::with(|arena| {
+//!     let input = arena.alloc(size)?;
+//!     let output = arena.alloc(size * 12)?;
+//!     ...
+//! })
+//! ```
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+pub const SIZE: usize = 32 * 1024;
+
+#[repr(C, align(4))]
+struct Storage([u8; SIZE]);
+
+static mut STORAGE: Storage = Storage([0; SIZE]);
+static IN_USE: AtomicBool = AtomicBool::new(false);
+
+/// What's left of the arena.
+pub struct Arena<'a> {
+    free: &'a mut [u8],
+}
+
+/// The arena has less than was asked for.
+#[derive(Copy, Clone, Debug)]
+pub struct OutOfMemory;
+
+impl<'a> Arena<'a> {
+    /// `len` bytes, rounded up to whole words.
+    pub fn alloc(&mut self, len: usize) -> Result<&'a mut [u8], OutOfMemory> {
+        let rounded = len.next_multiple_of(4);
+        if rounded > self.free.len() {
+            log::error!(
+                "arena: {} bytes wanted, {} left of {}",
+                len,
+                self.free.len(),
+                SIZE
+            );
+            return Err(OutOfMemory);
+        }
+        let (taken, rest) = core::mem::take(&mut self.free).split_at_mut(rounded);
+        self.free = rest;
+        Ok(&mut taken[..len])
+    }
+}
+
+/// Run `f` with the arena. Panics if it's already lent out.
+pub fn with<R>(f: impl FnOnce(&mut Arena) -> R) -> R {
+    // Only ever used from the main loop, a load and a store will do.
+    assert!(!IN_USE.load(Ordering::Acquire), "arena already in use");
+    IN_USE.store(true, Ordering::Release);
+
+    #[allow(static_mut_refs)]
+    let mut arena = Arena {
+        free: unsafe { &mut STORAGE.0 },
+    };
+    let result = f(&mut arena);
+
+    IN_USE.store(false, Ordering::Release);
+    result
+}
@@ -0,0 +1,279 @@
+//! Compact binary logging.
+//!
+//! With the `binary-log` feature, [`blog!`] records carry the address of an
+//! interned format string and the raw arguments instead of formatted text,
+//! and regular `log` records are framed as text so one stream carries both.
+//! The format strings are placed in the `.blog_strings` section which isn't
+//! loaded to the target (see `blog.x`), the host decoder reads them from the
+//! ELF file:
+//!
+//! ```sh
+//! cargo build --release --features binary-log
+//! cd host && cargo run --bin blog-decode -- \
+//!     ../target/thumbv6m-none-eabi/release/pico-pio-dma-test capture.bin
+//! ```
+//!
+//! Without the feature, [`blog!`] is `log::log!`.
+//!
+//! Frame: `b1 06 <len u8> <payload> <crc16 le>`, the CRC-16/CCITT-FALSE
+//! covers the length and the payload. Payload:
+//!
+//! | Field     | Encoding                                             |
+//! |-----------|------------------------------------------------------|
+//! | kind      | u8: 0 interned, 1 text                               |
+//! | level     | u8: `log::Level as u8` (1 = error .. 5 = trace)      |
+//! |           | in the low nibble, the core number in the high one   |
+//! | timestamp | varint, microseconds since boot                      |
+//! | interned  | u32 le string address, then the arguments            |
+//! | text      | module, file, line varint, message                   |
+//!
+//! An interned string is `module\x1ffile\x1fline\x1fformat\0`.
+//! Every argument is a tag byte followed by the value, see [`Tag`].
+//! Strings and byte slices are a varint length followed by the bytes.
+
+#![cfg_attr(not(feature = "binary-log"), allow(dead_code))]
+
+pub const SYNC: [u8; 2] = [0xb1, 0x06];
+
+const MAX_PAYLOAD: usize = 255;
+
+#[repr(u8)]
+pub enum Kind {
+    Interned = 0,
+    Text = 1,
+}
+
+#[repr(u8)]
+pub enum Tag {
+    /// Varint.
+    Unsigned = 0,
+    /// Zigzag varint.
+    Signed = 1,
+    /// u8, 0 or 1.
+    Bool = 2,
+    /// Varint code point.
+    Char = 3,
+    /// Varint length and UTF-8 bytes.
+    Str = 4,
+    /// f32 le.
+    F32 = 5,
+    /// Varint length and bytes.
+    Bytes = 6,
+}
+
+/// Log with an interned format string, like `log::log!`.
+///
+/// Arguments must implement [`blog::Arg`](Arg): integers, `bool`, `char`,
+/// `f32`, strings and byte slices. The host decoder understands the
+/// `{}`, `{:?}`, `{:x}`, `{:X}` and `{:b}` placeholders with fill, width
+/// and `#`.
+#[macro_export]
+macro_rules! blog {
+    ($level:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "binary-log")]
+        if log::log_enabled!($level) {
+            const FMT: &str = concat!(
+                module_path!(), "\x1f", file!(), "\x1f", line!(), "\x1f", $fmt, "\0"
+            );
+            #[link_section = ".blog_strings"]
+            static INTERNED: [u8; FMT.len()] = $crate::blog::intern(FMT);
+            $crate::blog::emit(
+                $level,
+                &INTERNED as *const _ as u32,
+                &[$(&$arg as &dyn $crate::blog::Arg),*],
+            );
+        }
+        #[cfg(not(feature = "binary-log"))]
+        log::log!($level, $fmt $(, $arg)*);
+    }};
+}
+
+pub const fn intern<const N: usize>(s: &str) -> [u8; N] {
+    let mut out = [0u8; N];
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+/// CRC-16/CCITT-FALSE.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub struct Frame {
+    buf: [u8; 2 + 1 + MAX_PAYLOAD + 2],
+    len: usize,
+}
+
+impl Frame {
+    fn new(kind: Kind, level: log::Level) -> Self {
+        let mut frame = Frame {
+            buf: [0; 2 + 1 + MAX_PAYLOAD + 2],
+            len: 3,
+        };
+        frame.buf[..2].copy_from_slice(&SYNC);
+        frame.push(kind as u8);
+        frame.push(level as u8 | crate::uart_log::core_id() << 4);
+        frame.varint(crate::time::time_us64());
+        frame
+    }
+
+    fn room(&self) -> usize {
+        3 + MAX_PAYLOAD - self.len
+    }
+
+    pub fn push(&mut self, b: u8) {
+        if self.room() > 0 {
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    pub fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.push(v as u8);
+    }
+
+    /// Length-prefixed bytes, cut to what fits.
+    pub fn bytes(&mut self, b: &[u8]) {
+        let n = b.len().min(self.room().saturating_sub(2));
+        self.varint(n as u64);
+        for &b in &b[..n] {
+            self.push(b);
+        }
+    }
+
+    pub fn str(&mut self, s: &str) {
+        let mut n = s.len().min(self.room().saturating_sub(2));
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.bytes(&s.as_bytes()[..n]);
+    }
+
+    fn finish(&mut self) -> &[u8] {
+        self.buf[2] = (self.len - 3) as u8;
+        let crc = crc16(&self.buf[2..self.len]);
+        self.buf[self.len..self.len + 2].copy_from_slice(&crc.to_le_bytes());
+        &self.buf[..self.len + 2]
+    }
+}
+
+/// A value that can be sent as a binary log argument.
+pub trait Arg {
+    fn encode(&self, frame: &mut Frame);
+}
+
+macro_rules! impl_arg {
+    ($tag:ident, $conv:expr, $($ty:ty),*) => {
+        $(impl Arg for $ty {
+            #[allow(clippy::unnecessary_cast)]
+            fn encode(&self, frame: &mut Frame) {
+                frame.push(Tag::$tag as u8);
+                frame.varint($conv(*self));
+            }
+        })*
+    };
+}
+
+impl_arg!(Unsigned, |v| v as u64, u8, u16, u32, u64, usize);
+impl_arg!(
+    Signed,
+    |v| {
+        let v = v as i64;
+        ((v << 1) ^ (v >> 63)) as u64
+    },
+    i8,
+    i16,
+    i32,
+    i64,
+    isize
+);
+impl_arg!(Char, |v| v as u64, char);
+
+impl Arg for bool {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push(Tag::Bool as u8);
+        frame.push(*self as u8);
+    }
+}
+
+impl Arg for f32 {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push(Tag::F32 as u8);
+        for b in self.to_le_bytes() {
+            frame.push(b);
+        }
+    }
+}
+
+impl Arg for str {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push(Tag::Str as u8);
+        frame.str(self);
+    }
+}
+
+impl Arg for [u8] {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push(Tag::Bytes as u8);
+        frame.bytes(self);
+    }
+}
+
+impl<const N: usize> Arg for [u8; N] {
+    fn encode(&self, frame: &mut Frame) {
+        self.as_slice().encode(frame);
+    }
+}
+
+impl<T: Arg + ?Sized> Arg for &T {
+    fn encode(&self, frame: &mut Frame) {
+        (**self).encode(frame);
+    }
+}
+
+impl<T: Arg + ?Sized> Arg for &mut T {
+    fn encode(&self, frame: &mut Frame) {
+        (**self).encode(frame);
+    }
+}
+
+pub fn emit(level: log::Level, id: u32, args: &[&dyn Arg]) {
+    let mut frame = Frame::new(Kind::Interned, level);
+    for b in id.to_le_bytes() {
+        frame.push(b);
+    }
+    for arg in args {
+        arg.encode(&mut frame);
+    }
+    crate::uart_log::write_record(frame.finish());
+}
+
+/// Frame a regular `log` record that was formatted on the target.
+pub fn emit_text(level: log::Level, module: &str, file: &str, line: u32, message: &[u8]) {
+    let mut frame = Frame::new(Kind::Text, level);
+    frame.str(module);
+    frame.str(file);
+    frame.varint(line as u64);
+    frame.bytes(message);
+    crate::uart_log::write_record(frame.finish());
+}
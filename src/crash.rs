@@ -0,0 +1,196 @@
+//! Panic handling that survives the reboot.
+//!
+//! A panic logs the message, the location, the busy DMA channels and the
+//! stack pointer and link register, keeps a crash record in RAM that isn't
+//! zeroed at startup, and resets the chip with the watchdog. Watchdog
+//! scratch registers 0 and 1 hold a marker and the line number in case the
+//! RAM record doesn't check out. The next boot reports and clears it.
+
+use crate::uart_log::LineBuffer;
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+/// In watchdog scratch 0 after a panic.
+const CRASH_MAGIC: u32 = 0x7061_6e63;
+
+const FILE_SIZE: usize = 48;
+const MESSAGE_SIZE: usize = 160;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CrashRecord {
+    time_us: u64,
+    sp: u32,
+    lr: u32,
+    line: u32,
+    core: u32,
+    file_len: u32,
+    message_len: u32,
+    file: [u8; FILE_SIZE],
+    message: [u8; MESSAGE_SIZE],
+    checksum: u32,
+}
+
+impl CrashRecord {
+    fn compute_checksum(&self) -> u32 {
+        // Everything before the checksum, as words.
+        let words = unsafe {
+            core::slice::from_raw_parts(
+                (self as *const Self).cast::<u32>(),
+                core::mem::offset_of!(CrashRecord, checksum) / 4,
+            )
+        };
+        words
+            .iter()
+            .fold(0x811c_9dc5u32, |sum, w| (sum ^ w).wrapping_mul(0x0100_0193))
+    }
+
+    fn file(&self) -> &str {
+        let len = (self.file_len as usize).min(FILE_SIZE);
+        core::str::from_utf8(&self.file[..len]).unwrap_or("?")
+    }
+
+    fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(MESSAGE_SIZE);
+        let bytes = &self.message[..len];
+        // Truncation may have split a character.
+        match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+}
+
+#[link_section = ".uninit.CRASH_RECORD"]
+static mut CRASH_RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+fn watchdog() -> &'static rp2040_pac::watchdog::RegisterBlock {
+    unsafe { &*rp2040_pac::WATCHDOG::PTR }
+}
+
+/// Reset everything but the oscillators, like the SDK's `watchdog_reboot`.
+fn reboot() -> ! {
+    let psm = unsafe { &*rp2040_pac::PSM::PTR };
+    psm.wdsel().write(|w| unsafe {
+        w.bits(0x0001_ffff);
+        w.rosc().clear_bit();
+        w.xosc().clear_bit()
+    });
+    watchdog().ctrl().modify(|_, w| w.trigger().set_bit());
+    loop {
+        cortex_m::asm::nop();
+    }
+}
+
+fn store(record: &CrashRecord) {
+    let watchdog = watchdog();
+    watchdog
+        .scratch0()
+        .write(|w| unsafe { w.bits(CRASH_MAGIC) });
+    watchdog
+        .scratch1()
+        .write(|w| unsafe { w.bits(record.line) });
+
+    let mut record = *record;
+    record.checksum = record.compute_checksum();
+    #[allow(static_mut_refs)]
+    unsafe {
+        CRASH_RECORD.write(record);
+    }
+}
+
+pub fn on_panic(info: &core::panic::PanicInfo) -> ! {
+    // A panic while handling a panic, e.g. in a log sink: just reboot.
+    if PANICKING.load(Ordering::Relaxed) {
+        reboot();
+    }
+    PANICKING.store(true, Ordering::Relaxed);
+
+    let sp = cortex_m::register::msp::read();
+    let lr: u32;
+    unsafe { core::arch::asm!("mov {}, lr", out(reg) lr) };
+
+    let (file, line) = info.location().map_or(("?", 0), |l| (l.file(), l.line()));
+    // Keep the end of long paths, that's where the file name is.
+    let file = &file[file.len().saturating_sub(FILE_SIZE)..];
+
+    let mut message = LineBuffer::<MESSAGE_SIZE>::new();
+    write!(message, "{}", info.message()).ok();
+
+    let mut record = CrashRecord {
+        time_us: crate::time::time_us64(),
+        sp,
+        lr,
+        line,
+        core: crate::uart_log::core_id() as u32,
+        file_len: file.len() as u32,
+        message_len: message.as_bytes().len() as u32,
+        file: [0; FILE_SIZE],
+        message: [0; MESSAGE_SIZE],
+        checksum: 0,
+    };
+    record.file[..file.len()].copy_from_slice(file.as_bytes());
+    record.message[..message.as_bytes().len()].copy_from_slice(message.as_bytes());
+    store(&record);
+
+    log::error!("panic at {}:{}: {}", file, line, info.message());
+    log::error!("sp {:08x} lr {:08x}", sp, lr);
+    crate::lax_dma::log_active_channels(log::Level::Error);
+    log::error!("rebooting");
+    log::logger().flush();
+
+    reboot();
+}
+
+/// Log the crash the previous boot ended with, if any, and forget it.
+/// Returns `true` if it ended with a panic or a watchdog reset.
+pub fn report_previous() -> bool {
+    let watchdog = watchdog();
+    let reason = watchdog.reason().read();
+    let reset_by_watchdog = reason.force().bit_is_set() || reason.timer().bit_is_set();
+    if reset_by_watchdog {
+        log::warn!(
+            "Reset by the watchdog ({})",
+            if reason.force().bit_is_set() {
+                "forced"
+            } else {
+                "timeout"
+            }
+        );
+    }
+
+    if watchdog.scratch0().read().bits() != CRASH_MAGIC {
+        return reset_by_watchdog;
+    }
+
+    #[allow(static_mut_refs)]
+    let record = unsafe { *CRASH_RECORD.assume_init_ref() };
+    if record.checksum == record.compute_checksum() {
+        log::error!(
+            "Previous boot panicked on core {} at {:08x} us, {}:{}: {}",
+            record.core,
+            record.time_us,
+            record.file(),
+            record.line,
+            record.message()
+        );
+        log::error!("sp {:08x} lr {:08x}", record.sp, record.lr);
+    } else {
+        log::error!(
+            "Previous boot panicked at line {}, the crash record is damaged",
+            watchdog.scratch1().read().bits()
+        );
+    }
+
+    watchdog.scratch0().write(|w| unsafe { w.bits(0) });
+    #[allow(static_mut_refs)]
+    unsafe {
+        CRASH_RECORD.assume_init_mut().checksum = !record.checksum;
+    }
+
+    true
+}
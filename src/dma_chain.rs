@@ -0,0 +1,350 @@
+//! Chained DMA channels: which channel a finished one starts, in what
+//! order, and how many times.
+//!
+//! The channels copy single words between `SLOTS`. Their addresses aren't
+//! reloaded when they finish, so with incrementing writes how far a write
+//! address has moved tells how many times the channel ran, and a channel
+//! that copies the word its predecessor wrote shows it ran after it. Only
+//! the channels the UART leaves free are used, see `dma_log::UART_CHANNELS`.
+
+use crate::dma_log;
+use crate::lax_dma;
+use crate::lax_dma::Config;
+use crate::lax_dma::Destination;
+use crate::lax_dma::LaxDmaWrite;
+use crate::lax_dma::Source;
+use crate::lax_dma::TxReq;
+use crate::lax_dma::TxSize;
+use crate::report;
+use crate::time;
+
+const TOKEN: u32 = 0x600d_f00d;
+const OTHER_TOKEN: u32 = 0x0bad_cafe;
+/// What the slots hold before a test.
+const STALE: u32 = 0xdead_beef;
+
+const SLOT_COUNT: usize = 32;
+
+const TIMEOUT: time::Duration = time::Duration::millis(10);
+/// How long a cycle runs before it's stopped.
+const CYCLE_TIME: time::Duration = time::Duration::micros(50);
+
+#[repr(C, align(4))]
+struct Slots([u32; SLOT_COUNT]);
+
+static mut SLOTS: Slots = Slots([0; SLOT_COUNT]);
+
+const CHANNELS: usize = lax_dma::CHANNELS as usize;
+
+/// At most one channel per DMA channel.
+type Channels = [Option<LaxDmaWrite>; CHANNELS];
+
+fn slot(i: usize) -> *mut u32 {
+    assert!(i < SLOT_COUNT);
+    unsafe { (core::ptr::addr_of_mut!(SLOTS.0) as *mut u32).add(i) }
+}
+
+fn read(i: usize) -> u32 {
+    unsafe { slot(i).read_volatile() }
+}
+
+/// Everything `STALE`, with `TOKEN` in slot 0 and `OTHER_TOKEN` in slot 3.
+fn reset_slots() {
+    for i in 0..SLOT_COUNT {
+        unsafe { slot(i).write_volatile(STALE) };
+    }
+    unsafe {
+        slot(0).write_volatile(TOKEN);
+        slot(3).write_volatile(OTHER_TOKEN);
+    }
+    cortex_m::asm::dsb();
+}
+
+/// `channel` copying a word from slot `from` to slot `to` when triggered,
+/// then starting `chain_to`. Writes increment unless it's a `cycle`.
+fn copy(channel: u8, chain_to: u8, from: usize, to: usize, increment_read: bool) -> LaxDmaWrite {
+    copy_with(channel, chain_to, from, to, increment_read, true)
+}
+
+fn copy_with(
+    channel: u8,
+    chain_to: u8,
+    from: usize,
+    to: usize,
+    increment_read: bool,
+    increment_write: bool,
+) -> LaxDmaWrite {
+    LaxDmaWrite::new_on(
+        channel,
+        chain_to,
+        Config {
+            high_priority: false,
+            word_size: TxSize::_32bit,
+            source: Source {
+                address: slot(from) as *const u8,
+                increment: increment_read,
+            },
+            destination: Destination {
+                address: slot(to) as *mut u8,
+                increment: increment_write,
+            },
+            tx_count: 1,
+            tx_req: TxReq::Permanent,
+            byte_swap: false,
+            start: false,
+        },
+    )
+}
+
+/// How many times `dma` ran since its write address was slot `to`.
+fn runs(dma: &LaxDmaWrite, to: usize) -> u32 {
+    dma.last_write_addr().wrapping_sub(slot(to) as u32) / 4
+}
+
+/// Unchain, then abort, see `LaxDmaWrite::unchain`.
+fn stop(dmas: &Channels) {
+    dmas.iter().flatten().for_each(LaxDmaWrite::unchain);
+    dmas.iter().flatten().for_each(LaxDmaWrite::abort);
+}
+
+/// Wait until `done` and every channel is idle. Stops them all and
+/// returns `false` if it takes too long.
+fn settle(name: &str, dmas: &Channels, done: impl Fn() -> bool) -> bool {
+    let idle = || dmas.iter().flatten().all(LaxDmaWrite::is_done);
+    if time::Deadline::after(TIMEOUT)
+        .wait_for(|| done() && idle())
+        .is_ok()
+    {
+        return true;
+    }
+    log::error!("!!! {} timed out", name);
+    lax_dma::log_active_channels(log::Level::Error);
+    stop(dmas);
+    false
+}
+
+fn finish(name: &str, start_us: u64, errors: usize) {
+    let duration_us = time::time_us64() - start_us;
+    if errors == 0 {
+        log::info!("*** {} passed", name);
+    } else {
+        log::error!("!!! {} failed, {} errors", name, errors);
+    }
+    report::result(&report::TestResult::check(name, None, duration_us, errors));
+}
+
+fn timed_out(name: &str, start_us: u64) {
+    report::result(&report::TestResult {
+        name,
+        channel: None,
+        duration_us: time::time_us64() - start_us,
+        status: report::Status::Fail,
+        mismatches: 0,
+        detail: "timed out",
+    });
+}
+
+/// `order[0]` chained to `order[1]` and so on, each copying what the one
+/// before it copied. Only the first one is triggered.
+fn linear(name: &str, order: &[u8]) {
+    log::info!("*** Running {}, channels {:?}", name, order);
+    let start_us = time::time_us64();
+    reset_slots();
+
+    let mut dmas: Channels = Default::default();
+    for (k, &channel) in order.iter().enumerate() {
+        let next = order.get(k + 1).copied().unwrap_or(channel);
+        dmas[k] = Some(copy(channel, next, k, k + 1, false));
+    }
+    let Some(head) = dmas[0].as_ref() else {
+        return;
+    };
+    let tail = dmas[order.len() - 1].as_ref().unwrap_or(head);
+
+    head.trigger_only();
+    if !settle(name, &dmas, || tail.tx_count_remaining() == 0) {
+        timed_out(name, start_us);
+        return;
+    }
+
+    let mut errors = 0;
+    for (k, dma) in dmas.iter().flatten().enumerate() {
+        let (ran, copied) = (runs(dma, k + 1), read(k + 1));
+        if ran != 1 || copied != TOKEN {
+            log::error!(
+                "!!! {}: channel {} ran {} times, copied {:08x}",
+                name,
+                order[k],
+                ran,
+                copied
+            );
+            errors += 1;
+        }
+    }
+    finish(name, start_us, errors);
+}
+
+/// Every channel chained to itself runs once and starts nothing.
+fn to_self(channels: &[u8]) {
+    let name = "dma_chain_self";
+    log::info!("*** Running {}, channels {:?}", name, channels);
+    let start_us = time::time_us64();
+
+    let mut errors = 0;
+    for &channel in channels {
+        reset_slots();
+        let mut dmas: Channels = Default::default();
+        dmas[0] = Some(copy(channel, channel, 0, 1, false));
+        let Some(dma) = dmas[0].as_ref() else {
+            continue;
+        };
+        dma.trigger_only();
+        if !settle(name, &dmas, || dma.tx_count_remaining() == 0) {
+            timed_out(name, start_us);
+            return;
+        }
+        // Time to start again if it were going to.
+        time::delay(time::Duration::micros(10));
+        let (ran, copied) = (runs(dma, 1), read(1));
+        if ran != 1 || copied != TOKEN {
+            log::error!(
+                "!!! {}: channel {} ran {} times, copied {:08x}",
+                name,
+                channel,
+                ran,
+                copied
+            );
+            errors += 1;
+        }
+    }
+    finish(name, start_us, errors);
+}
+
+/// `a` and `b` chained to each other run until stopped. One of them is
+/// always busy while they do.
+fn cycle(a: u8, b: u8) {
+    let name = "dma_chain_cycle";
+    log::info!("*** Running {}, channels {} and {}", name, a, b);
+    let start_us = time::time_us64();
+    reset_slots();
+
+    let mut dmas: Channels = Default::default();
+    dmas[0] = Some(copy_with(a, b, 0, 16, false, false));
+    dmas[1] = Some(copy_with(b, a, 3, 17, false, false));
+    if let Some(dma) = dmas[0].as_ref() {
+        dma.trigger_only();
+    }
+
+    let mut errors = 0;
+    let busy = || !dmas.iter().flatten().all(LaxDmaWrite::is_done);
+    let deadline = time::Deadline::after(CYCLE_TIME);
+    let mut seen_busy = 0;
+    while !deadline.expired() {
+        seen_busy += busy() as u32;
+    }
+    if seen_busy == 0 {
+        log::error!(
+            "!!! {}: not running after {} us",
+            name,
+            CYCLE_TIME.to_micros()
+        );
+        errors += 1;
+    }
+
+    stop(&dmas);
+    let copied = read(17);
+    time::delay(time::Duration::micros(10));
+    if busy() {
+        log::error!("!!! {}: still running after the abort", name);
+        lax_dma::log_active_channels(log::Level::Error);
+        errors += 1;
+    }
+    if read(16) != TOKEN || copied != OTHER_TOKEN {
+        log::error!("!!! {}: copied {:08x} and {:08x}", name, read(16), copied);
+        errors += 1;
+    }
+    finish(name, start_us, errors);
+}
+
+/// `a` and `b` both chained to `c`, which runs after each of them.
+fn fan_in(a: u8, b: u8, c: u8) {
+    let name = "dma_chain_fan_in";
+    log::info!("*** Running {}, channels {} and {} to {}", name, a, b, c);
+    let start_us = time::time_us64();
+    reset_slots();
+
+    // `c` copies slots 1 and 2, which `a` and `b` fill, to 8 and 9.
+    let mut dmas: Channels = Default::default();
+    dmas[0] = Some(copy(a, c, 0, 1, false));
+    dmas[1] = Some(copy(b, c, 3, 2, false));
+    dmas[2] = Some(copy(c, c, 1, 8, true));
+    let [Some(dma_a), Some(dma_b), Some(dma_c), ..] = &dmas else {
+        return;
+    };
+
+    dma_a.trigger_only();
+    if !settle(name, &dmas, || runs(dma_c, 8) >= 1) {
+        timed_out(name, start_us);
+        return;
+    }
+    dma_b.trigger_only();
+    if !settle(name, &dmas, || runs(dma_c, 8) >= 2) {
+        timed_out(name, start_us);
+        return;
+    }
+    time::delay(time::Duration::micros(10));
+
+    let mut errors = 0;
+    for (channel, ran, expected) in [
+        (a, runs(dma_a, 1), 1),
+        (b, runs(dma_b, 2), 1),
+        (c, runs(dma_c, 8), 2),
+    ] {
+        if ran != expected {
+            log::error!(
+                "!!! {}: channel {} ran {} times, expected {}",
+                name,
+                channel,
+                ran,
+                expected
+            );
+            errors += 1;
+        }
+    }
+    if read(8) != TOKEN || read(9) != OTHER_TOKEN {
+        log::error!(
+            "!!! {}: channel {} copied {:08x} {:08x}, expected {:08x} {:08x}",
+            name,
+            c,
+            read(8),
+            read(9),
+            TOKEN,
+            OTHER_TOKEN
+        );
+        errors += 1;
+    }
+    finish(name, start_us, errors);
+}
+
+pub fn run_chain_tests() {
+    let mut free = [0u8; CHANNELS];
+    let mut count = 0;
+    for channel in 0..lax_dma::CHANNELS {
+        if dma_log::UART_CHANNELS & 1 << channel == 0 {
+            free[count] = channel;
+            count += 1;
+        }
+    }
+    let free = &free[..count];
+
+    linear("dma_chain_linear", free);
+    // Chaining backwards and across the channel numbers.
+    let mut shuffled = [0u8; CHANNELS];
+    for (i, &channel) in free.iter().enumerate() {
+        shuffled[(i * 7 + 3) % count] = channel;
+    }
+    linear("dma_chain_shuffled", &shuffled[..count]);
+    to_self(free);
+    cycle(free[2], free[count - 1]);
+    fan_in(free[1], free[count - 2], free[count / 2]);
+}
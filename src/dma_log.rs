@@ -0,0 +1,267 @@
+//! Non-blocking UART log transport.
+//!
+//! Records are copied into a RAM ring buffer and drained to the UART by a
+//! DMA channel paced by the UART TX DREQ, so logging costs a copy instead of
+//! waiting for the bytes to go out at the baud rate. The ring is only
+//! serviced when a record is written or on `flush`: nothing runs in the
+//! background except the DMA transfer in flight.
+//!
+//! Works with either UART. DMA channel 11 is reserved for this, the
+//! experiments use the low ones.
+//!
+//! `DmaUartReader` is the other direction, for the shell: channel 10 copies
+//! whatever arrives into a small ring that is read when convenient.
+
+use crate::lax_dma::Config;
+use crate::lax_dma::Destination;
+use crate::lax_dma::LaxDmaWrite;
+use crate::lax_dma::Source;
+use crate::lax_dma::TxReq;
+use crate::lax_dma::TxSize;
+use crate::log_sink::LogSink;
+use core::fmt::Write;
+use rp2040_hal::dma;
+use rp2040_hal::uart::Enabled;
+use rp2040_hal::uart::UartDevice;
+use rp2040_hal::uart::UartPeripheral;
+use rp2040_hal::uart::ValidUartPinout;
+
+pub const RING_SIZE: usize = 8192;
+
+type LogDmaChannel = dma::CH11;
+const RX_DMA_CHANNEL: u8 = 10;
+
+/// The DMA channels the log and the shell take, as a bit mask.
+pub const UART_CHANNELS: u16 = 1 << RX_DMA_CHANNEL | 1 << 11;
+
+const RX_RING_BITS: u8 = 8;
+pub const RX_RING_SIZE: usize = 1 << RX_RING_BITS;
+
+/// Aligned for the DMA ring wrap.
+#[repr(C, align(256))]
+pub struct RxRing(pub [u8; RX_RING_SIZE]);
+
+pub struct DmaUartWriter {
+    ring: &'static mut [u8; RING_SIZE],
+    uart: &'static rp2040_pac::uart0::RegisterBlock,
+    tx_req: TxReq,
+    /// Total bytes ever written into the ring, wrapping.
+    written: usize,
+    /// Total bytes the DMA has finished sending, wrapping.
+    sent: usize,
+    /// The transfer in flight and its length.
+    in_flight: Option<(LaxDmaWrite, usize)>,
+    /// Records dropped since the last one that fit.
+    dropped: u32,
+}
+
+impl DmaUartWriter {
+    /// Drain `ring` to the UART `D`, which must be enabled with DMA.
+    pub fn new<D: UartDevice>(ring: &'static mut [u8; RING_SIZE]) -> Self {
+        let (uart, tx_req) = match D::ID {
+            0 => (rp2040_pac::UART0::PTR, TxReq::Uart0Tx),
+            _ => (rp2040_pac::UART1::PTR, TxReq::Uart1Tx),
+        };
+        DmaUartWriter {
+            ring,
+            uart: unsafe { &*uart },
+            tx_req,
+            written: 0,
+            sent: 0,
+            in_flight: None,
+            dropped: 0,
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.written.wrapping_sub(self.sent)
+    }
+
+    fn free(&self) -> usize {
+        RING_SIZE - self.pending()
+    }
+
+    fn copy_in(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.ring[self.written % RING_SIZE] = b;
+            self.written = self.written.wrapping_add(1);
+        }
+    }
+
+    /// Write a whole record, or drop it if it doesn't fit. Returns `false`
+    /// if dropped.
+    pub fn write_record(&mut self, record: &[u8]) -> bool {
+        self.pump();
+
+        let mut notice = crate::uart_log::LineBuffer::<40>::new();
+        if self.dropped != 0 {
+            write!(notice, "{} messages dropped\r\n", self.dropped).ok();
+        }
+        if notice.as_bytes().len() + record.len() > self.free() {
+            self.dropped += 1;
+            return false;
+        }
+
+        self.dropped = 0;
+        self.copy_in(notice.as_bytes());
+        self.copy_in(record);
+        self.pump();
+
+        true
+    }
+
+    /// Retire the finished transfer and start the next one if there's
+    /// anything left to send.
+    pub fn pump(&mut self) {
+        if let Some((dma, len)) = self.in_flight.as_ref() {
+            if !dma.is_done() {
+                return;
+            }
+            self.sent = self.sent.wrapping_add(*len);
+            self.in_flight = None;
+        }
+
+        let start = self.sent % RING_SIZE;
+        let len = self.pending().min(RING_SIZE - start);
+        if len == 0 {
+            return;
+        }
+
+        cortex_m::asm::dsb();
+        let dma = LaxDmaWrite::new::<LogDmaChannel>(Config {
+            high_priority: false,
+            word_size: TxSize::_8bit,
+            source: Source {
+                address: self.ring[start..].as_ptr(),
+                increment: true,
+            },
+            destination: Destination {
+                address: self.uart.uartdr().as_ptr() as *mut u8,
+                increment: false,
+            },
+            tx_count: len as u32,
+            tx_req: self.tx_req,
+            byte_swap: false,
+            start: true,
+        });
+        self.in_flight = Some((dma, len));
+    }
+
+    /// Wait until everything written so far has left the ring and the
+    /// UART has shifted out the last bit. A full ring takes most of a
+    /// second, that's not a hang.
+    pub fn flush(&mut self) {
+        while self.pending() != 0 {
+            self.pump();
+            crate::guard::feed();
+        }
+        while self.uart.uartfr().read().busy().bit_is_set() {}
+    }
+}
+
+/// An enabled UART logged to through a `DmaUartWriter`.
+pub struct DmaUart<D: UartDevice, P: ValidUartPinout<D>> {
+    /// Kept to keep the pins configured, DMA writes to the UART directly.
+    _uart: UartPeripheral<Enabled, D, P>,
+    ring: DmaUartWriter,
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>> DmaUart<D, P> {
+    pub fn new(uart: UartPeripheral<Enabled, D, P>, ring: &'static mut [u8; RING_SIZE]) -> Self {
+        DmaUart {
+            _uart: uart,
+            ring: DmaUartWriter::new::<D>(ring),
+        }
+    }
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>> LogSink for DmaUart<D, P> {
+    fn write(&mut self, record: &[u8]) {
+        self.ring.write_record(record);
+    }
+
+    fn flush(&mut self) {
+        self.ring.flush();
+    }
+}
+
+/// Receives from a UART by DMA into a ring. More than `RX_RING_SIZE` bytes
+/// unread are overwritten.
+pub struct DmaUartReader {
+    ring: &'static mut RxRing,
+    uart: &'static rp2040_pac::uart0::RegisterBlock,
+    rx_req: TxReq,
+    dma: Option<LaxDmaWrite>,
+    /// Position in the ring read up to.
+    read: usize,
+}
+
+impl DmaUartReader {
+    /// Receive from the UART `D`, which must be enabled with DMA.
+    pub fn new<D: UartDevice>(ring: &'static mut RxRing) -> Self {
+        let (uart, rx_req) = match D::ID {
+            0 => (rp2040_pac::UART0::PTR, TxReq::Uart0Rx),
+            _ => (rp2040_pac::UART1::PTR, TxReq::Uart1Rx),
+        };
+        let mut reader = DmaUartReader {
+            ring,
+            uart: unsafe { &*uart },
+            rx_req,
+            dma: None,
+            read: 0,
+        };
+        reader.start();
+        reader
+    }
+
+    fn start(&mut self) {
+        // Dropping resets the channel, do it before setting it up again.
+        self.dma = None;
+        let dma = LaxDmaWrite::new_on(
+            RX_DMA_CHANNEL,
+            RX_DMA_CHANNEL,
+            Config {
+                high_priority: false,
+                word_size: TxSize::_8bit,
+                source: Source {
+                    address: self.uart.uartdr().as_ptr() as *const u8,
+                    increment: false,
+                },
+                destination: Destination {
+                    address: self.ring.0.as_mut_ptr(),
+                    increment: true,
+                },
+                // Days at any baud rate, restarted when it runs out.
+                tx_count: u32::MAX,
+                tx_req: self.rx_req,
+                byte_swap: false,
+                start: false,
+            },
+        );
+        dma.set_write_ring(RX_RING_BITS);
+        dma.trigger();
+        self.read = 0;
+        self.dma = Some(dma);
+    }
+
+    /// Copy what arrived since the last call into `buf`, returns how much.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let Some(dma) = self.dma.as_ref() else {
+            return 0;
+        };
+        let base = self.ring.0.as_ptr() as u32;
+        let written = (dma.last_write_addr().wrapping_sub(base)) as usize % RX_RING_SIZE;
+
+        let mut n = 0;
+        while self.read != written && n < buf.len() {
+            buf[n] = self.ring.0[self.read];
+            self.read = (self.read + 1) % RX_RING_SIZE;
+            n += 1;
+        }
+
+        if n == 0 && dma.is_done() {
+            self.start();
+        }
+        n
+    }
+}
@@ -0,0 +1,208 @@
+//! Randomized memory to memory DMA tests against a software model.
+//!
+//! Every case draws the transfer size, element count, source and
+//! destination offsets, increments and byte swap from a seeded `Rng`, runs
+//! on a DMA channel between two buffers of random bytes, and compares the
+//! whole destination buffer with what `model` leaves in a copy of it, so
+//! stray writes show up too. The first failing case ends the run and logs
+//! the seed: `set seed <seed>` and `run dma_random` in the shell repeat the
+//! same cases.
+
+use crate::hexdump;
+use crate::lax_dma::Config;
+use crate::lax_dma::Destination;
+use crate::lax_dma::LaxDmaWrite;
+use crate::lax_dma::Source;
+use crate::lax_dma::TxReq;
+use crate::lax_dma::TxSize;
+use crate::report;
+use crate::rng::Rng;
+use crate::time;
+use crate::uart_log::LineBuffer;
+use core::fmt;
+use core::fmt::Write;
+
+/// Cases in a run.
+pub const CASES: u32 = 256;
+
+const BUFFER_SIZE: usize = 512;
+/// Largest offset into the buffers a transfer starts at.
+const MAX_OFFSET: usize = 64;
+
+const DMA_TIMEOUT: time::Duration = time::Duration::millis(100);
+
+#[repr(C, align(4))]
+struct Buffer([u8; BUFFER_SIZE]);
+
+static mut SRC: Buffer = Buffer([0; BUFFER_SIZE]);
+static mut DST: Buffer = Buffer([0; BUFFER_SIZE]);
+static mut EXPECTED: Buffer = Buffer([0; BUFFER_SIZE]);
+
+fn width(word_size: TxSize) -> usize {
+    1 << word_size as u8
+}
+
+#[derive(Copy, Clone)]
+pub struct Case {
+    pub word_size: TxSize,
+    pub count: u32,
+    /// Offsets into the buffers, aligned to the word size.
+    pub src_offset: usize,
+    pub dst_offset: usize,
+    pub increment_src: bool,
+    pub increment_dst: bool,
+    pub byte_swap: bool,
+}
+
+impl Case {
+    pub fn random(rng: &mut Rng) -> Self {
+        let word_size = [TxSize::_8bit, TxSize::_16bit, TxSize::_32bit][rng.below(3) as usize];
+        let width = width(word_size);
+        let offsets = (MAX_OFFSET / width) as u32;
+        // Mostly short transfers, now and then as long as the buffers allow.
+        let longest = ((BUFFER_SIZE - MAX_OFFSET) / width) as u32;
+        let limit = [4, 32, longest][rng.below(3) as usize];
+        Case {
+            word_size,
+            count: 1 + rng.below(limit),
+            src_offset: rng.below(offsets) as usize * width,
+            dst_offset: rng.below(offsets) as usize * width,
+            increment_src: rng.bool(),
+            increment_dst: rng.bool(),
+            byte_swap: rng.bool(),
+        }
+    }
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} x {}-bit, src +{:#x}{}, dst +{:#x}{}{}",
+            self.count,
+            width(self.word_size) * 8,
+            self.src_offset,
+            if self.increment_src { " inc" } else { "" },
+            self.dst_offset,
+            if self.increment_dst { " inc" } else { "" },
+            if self.byte_swap { ", byte swap" } else { "" }
+        )
+    }
+}
+
+/// What `case` does to `dst`, reading from `src`. Elements are read and
+/// written whole, a byte swap reverses the bytes of each.
+pub fn model(case: &Case, src: &[u8], dst: &mut [u8]) {
+    let width = width(case.word_size);
+    for i in 0..case.count as usize {
+        let from = case.src_offset + if case.increment_src { i * width } else { 0 };
+        let to = case.dst_offset + if case.increment_dst { i * width } else { 0 };
+        let mut element = [0u8; 4];
+        element[..width].copy_from_slice(&src[from..from + width]);
+        if case.byte_swap {
+            element[..width].reverse();
+        }
+        dst[to..to + width].copy_from_slice(&element[..width]);
+    }
+}
+
+/// Run `cases` random cases on `channel`. A `seed` of 0 picks one.
+pub fn run(channel: u8, seed: u32, cases: u32) {
+    let seed = if seed == 0 {
+        Rng::seed_from_time()
+    } else {
+        seed
+    };
+    log::info!(
+        "*** Running {} random DMA cases on channel {}, seed {:#010x}",
+        cases,
+        channel,
+        seed
+    );
+
+    #[allow(static_mut_refs)]
+    let (src, dst, expected) = unsafe { (&mut SRC.0, &mut DST.0, &mut EXPECTED.0) };
+    let mut rng = Rng::new(seed);
+    let mut duration_us = 0;
+
+    for i in 0..cases {
+        // One result for all of them, see `guard`.
+        crate::guard::feed();
+        let case = Case::random(&mut rng);
+        log::debug!("dma_random case {}: {}", i, case);
+        rng.fill(src);
+        rng.fill(dst);
+        expected.copy_from_slice(dst);
+        model(&case, src, expected);
+
+        let dma = LaxDmaWrite::new_on(
+            channel,
+            channel,
+            Config {
+                high_priority: false,
+                word_size: case.word_size,
+                source: Source {
+                    address: src[case.src_offset..].as_ptr(),
+                    increment: case.increment_src,
+                },
+                destination: Destination {
+                    address: dst[case.dst_offset..].as_mut_ptr(),
+                    increment: case.increment_dst,
+                },
+                tx_count: case.count,
+                tx_req: TxReq::Permanent,
+                byte_swap: case.byte_swap,
+                start: false,
+            },
+        );
+        let start_us = time::time_us64();
+        dma.trigger();
+        let done = dma.wait_timeout(DMA_TIMEOUT);
+        duration_us += time::time_us64() - start_us;
+
+        // A timeout is logged by `wait_timeout`.
+        let mismatches = match done {
+            Ok(()) => hexdump::diff(log::Level::Error, "dma_random", expected, dst),
+            Err(_) => 0,
+        };
+        if done.is_err() || mismatches != 0 {
+            let mut detail = LineBuffer::<160>::new();
+            write!(
+                detail,
+                "case {} of seed {:#010x}{}: {}",
+                i,
+                seed,
+                if done.is_err() { " timed out" } else { "" },
+                case
+            )
+            .ok();
+            let detail = core::str::from_utf8(detail.as_bytes()).unwrap_or_default();
+            log::error!("!!! dma_random failed, {}", detail);
+            log::error!(
+                "!!! repeat with `set seed {:#x}` and `run dma_random`",
+                seed
+            );
+            report::result(&report::TestResult {
+                name: "dma_random",
+                channel: Some(channel),
+                duration_us,
+                status: report::Status::Fail,
+                mismatches,
+                detail,
+            });
+            return;
+        }
+    }
+
+    log::info!(
+        "*** dma_random passed {} cases in {} us",
+        cases,
+        duration_us
+    );
+    report::result(&report::TestResult::check(
+        "dma_random",
+        Some(channel),
+        duration_us,
+        0,
+    ));
+}
@@ -1,3 +1,7 @@
+use crate::arena;
+use crate::dma_log;
+use crate::golden;
+use crate::hexdump;
 use crate::lax_dma;
 use crate::lax_dma::Config;
 use crate::lax_dma::Destination;
@@ -5,6 +9,16 @@ use crate::lax_dma::LaxDmaWrite;
 use crate::lax_dma::Source;
 use crate::lax_dma::TxReq;
 use crate::lax_dma::TxSize;
+use crate::lax_pio;
+use crate::pattern::Pattern;
+use crate::pio_programs;
+use crate::pio_timing;
+use crate::profile;
+use crate::report;
+use crate::scaler;
+use crate::time;
+use crate::uart_log::LineBuffer;
+use core::fmt::Write;
 use rp2040_hal::dma;
 use rp2040_hal::pio::PIOExt;
 use rp2040_pac::PIO0;
@@ -18,9 +32,24 @@ pub enum MonochromeColor {
     Bpp4 = 4,
 }
 
+/// What an experiment streams, the shell's `size` and `pattern`.
+#[derive(Copy, Clone)]
+pub struct Input {
+    /// In bytes, a multiple of 4.
+    pub size: usize,
+    pub pattern: Pattern,
+}
+
+/// Far longer than any of the transfers here should take.
+const DMA_TIMEOUT: time::Duration = time::Duration::millis(100);
+
+/// Repetitions of a passing DMA test for `profile`.
+const PROFILE_RUNS: usize = 16;
+
+#[derive(Copy, Clone)]
 struct TestConfig {
-    src: &'static mut [u8; 4],
-    dst: &'static mut [u8; 4],
+    src: [u8; 4],
+    dst: [u8; 4],
     expected: [u8; 4],
     word_size: lax_dma::TxSize,
     byte_swap: bool,
@@ -29,10 +58,10 @@ struct TestConfig {
     test_name: &'static str,
 }
 
-fn run_dma_test<CHID: dma::ChannelIndex>(config: TestConfig) {
+fn run_dma_test(channel: u8, config: TestConfig) {
     let TestConfig {
         src,
-        dst,
+        mut dst,
         expected,
         word_size,
         byte_swap,
@@ -41,7 +70,12 @@ fn run_dma_test<CHID: dma::ChannelIndex>(config: TestConfig) {
         test_name,
     } = config;
 
-    log::info!("*** Running DMA test {}, channel {}", test_name, CHID::id());
+    crate::blog!(
+        log::Level::Info,
+        "*** Running DMA test {}, channel {}",
+        test_name,
+        channel
+    );
 
     // Calculate the transaction count based on the word size
     let tx_count = match word_size {
@@ -68,7 +102,7 @@ fn run_dma_test<CHID: dma::ChannelIndex>(config: TestConfig) {
         start: false,
     };
 
-    let dma = lax_dma::LaxDmaWrite::new::<CHID>(dma_config);
+    let dma = lax_dma::LaxDmaWrite::new_on(channel, channel, dma_config);
     log::debug!("DMA source addr: {:x}", src.as_ptr() as usize);
     log::debug!("DMA dest addr: {:x}", dst.as_ptr() as usize);
     log::debug!("src: {:?}", src);
@@ -76,8 +110,15 @@ fn run_dma_test<CHID: dma::ChannelIndex>(config: TestConfig) {
 
     // Start the DMA transfer
     log::debug!("Starting DMA");
+    let start_us = time::time_us64();
     dma.trigger();
-    dma.wait();
+    let done = dma.wait_timeout(DMA_TIMEOUT);
+    let duration_us = time::time_us64() - start_us;
+    if done.is_err() {
+        log::error!("!!! {} timed out", test_name);
+        report_failed(test_name, Some(channel), duration_us, "timed out");
+        return;
+    }
     log::debug!("DMA done");
 
     // Log final state
@@ -92,7 +133,14 @@ fn run_dma_test<CHID: dma::ChannelIndex>(config: TestConfig) {
     log::debug!("DMA tx count remaining: {:?}", dma.tx_count_remaining());
 
     // Validate the result
-    if dst != &expected {
+    let mismatches = hexdump::diff(log::Level::Error, test_name, &expected, &dst);
+    report::result(&report::TestResult::check(
+        test_name,
+        Some(channel),
+        duration_us,
+        mismatches,
+    ));
+    if mismatches != 0 {
         log::error!(
             "!!! {} failed! Expected: {:?}, got: {:?}",
             test_name,
@@ -106,6 +154,16 @@ fn run_dma_test<CHID: dma::ChannelIndex>(config: TestConfig) {
             expected,
             dst
         );
+
+        // Again for the cycle count, the microsecond timer can't tell.
+        for _ in 0..PROFILE_RUNS {
+            let dma = lax_dma::LaxDmaWrite::new_on(channel, channel, dma_config);
+            let _scope = profile::Scope::new(test_name);
+            dma.trigger();
+            if dma.wait_timeout(DMA_TIMEOUT).is_err() {
+                break;
+            }
+        }
     }
 }
 
@@ -113,8 +171,8 @@ pub fn run_dma_tests() {
     // Define the test configurations
     let tests = [
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 44, 45],
             word_size: lax_dma::TxSize::_8bit,
             byte_swap: false,
@@ -123,8 +181,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_8bit",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 44, 45],
             word_size: lax_dma::TxSize::_16bit,
             byte_swap: false,
@@ -133,8 +191,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_16bit",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 44, 45],
             word_size: lax_dma::TxSize::_32bit,
             byte_swap: false,
@@ -143,8 +201,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_32bit",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 44, 45],
             word_size: lax_dma::TxSize::_8bit,
             byte_swap: true,
@@ -153,8 +211,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_8bit_byte_swap",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [43, 42, 45, 44],
             word_size: lax_dma::TxSize::_16bit,
             byte_swap: true,
@@ -163,8 +221,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_16bit_byte_swap",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [45, 44, 43, 42],
             word_size: lax_dma::TxSize::_32bit,
             byte_swap: true,
@@ -173,8 +231,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_32bit_byte_swap",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 42, 42, 42],
             word_size: lax_dma::TxSize::_8bit,
             byte_swap: false,
@@ -183,8 +241,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_8bit_fill",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 42, 43],
             word_size: lax_dma::TxSize::_16bit,
             byte_swap: false,
@@ -193,8 +251,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_16bit_fill",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 44, 45],
             word_size: lax_dma::TxSize::_32bit,
             byte_swap: false,
@@ -203,8 +261,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_32bit_fill",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [45, 0, 0, 0],
             word_size: lax_dma::TxSize::_8bit,
             byte_swap: false,
@@ -213,8 +271,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_8bit_dst_fixed",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [44, 45, 0, 0],
             word_size: lax_dma::TxSize::_16bit,
             byte_swap: false,
@@ -223,8 +281,8 @@ pub fn run_dma_tests() {
             test_name: "dma_test_16bit_dst_fixed",
         },
         TestConfig {
-            src: cortex_m::singleton!(: [u8; 4] = [42, 43, 44, 45]).unwrap(),
-            dst: cortex_m::singleton!(: [u8; 4] = [0; 4]).unwrap(),
+            src: [42, 43, 44, 45],
+            dst: [0; 4],
             expected: [42, 43, 44, 45],
             word_size: lax_dma::TxSize::_32bit,
             byte_swap: false,
@@ -234,20 +292,155 @@ pub fn run_dma_tests() {
         },
     ];
 
-    for test in tests.into_iter() {
-        run_dma_test::<dma::CH5>(test);
+    // Every channel, there could be one that's different.
+    for channel in 0..lax_dma::CHANNELS {
+        if dma_log::UART_CHANNELS & 1 << channel != 0 {
+            report::result(&report::TestResult {
+                name: "dma_tests",
+                channel: Some(channel),
+                duration_us: 0,
+                status: report::Status::Skip,
+                mismatches: 0,
+                detail: "taken by the UART",
+            });
+            continue;
+        }
+        for test in tests {
+            run_dma_test(channel, test);
+        }
     }
 }
 
-pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
+/// Copy `input` with 32-bit transfers on `channel`, for trying what the
+/// fixed tests don't cover from the shell.
+pub fn run_dma_copy(channel: u8, input: Input) {
+    arena::with(|arena| dma_copy(arena, channel, input));
+}
+
+fn dma_copy(arena: &mut arena::Arena, channel: u8, input: Input) {
+    let Input { size, pattern } = input;
+    log::info!(
+        "*** Running DMA copy of {} bytes of {} on channel {}",
+        size,
+        pattern,
+        channel
+    );
+
+    let (Ok(src), Ok(dst)) = (arena.alloc(size), arena.alloc(size)) else {
+        report_failed("dma_copy", Some(channel), 0, "too big for the arena");
+        return;
+    };
+    pattern.fill(src);
+    dst.fill(!pattern.byte(0));
+
+    let dma = LaxDmaWrite::new_on(
+        channel,
+        channel,
+        Config {
+            high_priority: false,
+            word_size: TxSize::_32bit,
+            source: Source {
+                address: src.as_ptr(),
+                increment: true,
+            },
+            destination: Destination {
+                address: dst.as_mut_ptr(),
+                increment: true,
+            },
+            tx_count: size as u32 / 4,
+            tx_req: TxReq::Permanent,
+            byte_swap: false,
+            start: false,
+        },
+    );
+    let start_us = time::time_us64();
+    dma.trigger();
+    let done = dma.wait_timeout(DMA_TIMEOUT);
+    let duration_us = time::time_us64() - start_us;
+    if done.is_err() {
+        log::error!("!!! dma_copy timed out");
+        report_failed("dma_copy", Some(channel), duration_us, "timed out");
+        return;
+    }
+
+    let mismatches = hexdump::diff(log::Level::Error, "dma_copy", src, dst);
+    report::result(&report::TestResult::check(
+        "dma_copy",
+        Some(channel),
+        duration_us,
+        mismatches,
+    ));
+    if mismatches == 0 {
+        log::info!("*** dma_copy passed in {} us", duration_us);
+    } else {
+        log::error!("!!! dma_copy failed");
+    }
+}
+
+/// Logs the measured DMA throughput next to the limit the PIO program can sustain.
+fn log_benchmark<const N: usize>(
+    name: &str,
+    program: &pio::Program<N>,
+    sm_config: &pio_timing::SmConfig,
+    sys_hz: u32,
+    bytes_in: usize,
+    bytes_out: usize,
+    elapsed_us: u64,
+) {
+    match pio_timing::analyze(program, sm_config) {
+        Ok(timing) => timing.log(name, sys_hz, sm_config.clkdiv),
+        Err(e) => log::error!("Can't time {}: {:?}", name, e),
+    }
+
+    let elapsed_us = elapsed_us.max(1);
+    crate::blog!(
+        log::Level::Info,
+        "*** {}: {} B in, {} B out in {} us, measured {} B/s in, {} B/s out",
+        name,
+        bytes_in,
+        bytes_out,
+        elapsed_us,
+        bytes_in as u64 * 1_000_000 / elapsed_us,
+        bytes_out as u64 * 1_000_000 / elapsed_us,
+    );
+}
+
+fn report_failed(name: &str, channel: Option<u8>, duration_us: u64, detail: &str) {
+    report::result(&report::TestResult {
+        name,
+        channel,
+        duration_us,
+        status: report::Status::Fail,
+        mismatches: 0,
+        detail,
+    });
+}
+
+pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS, input: Input, sys_hz: u32) {
+    arena::with(|arena| invert_twice(arena, pio, resets, input, sys_hz));
+}
+
+fn invert_twice(
+    arena: &mut arena::Arena,
+    pio: PIO0,
+    resets: &mut RESETS,
+    input: Input,
+    sys_hz: u32,
+) {
     // | DMA Channel | Source (Read Address)      | Destination (Write Address) | FIFO Connection           | Shift Register              |
     // |-------------|----------------------------|-----------------------------|---------------------------|-----------------------------|
     // | DMA 1 (TX)  | RAM Buffer                 | PIO TX FIFO (PIO0_TXF_SM0)  | TX FIFO feeds OSR         | OSR (Output Shift Register) |
     // | DMA 2 (RX)  | PIO RX FIFO (PIO0_RXF_SM0) | RAM Buffer                  | RX FIFO receives from ISR | ISR (Input Shift Register)  |
 
-    const SIZE: usize = 32;
-    let input_buffer = [0x55u8; SIZE];
-    let mut output_buffer = [0u8; SIZE];
+    let size = input.size;
+    let (Ok(input_buffer), Ok(output_buffer), Ok(expected)) =
+        (arena.alloc(size), arena.alloc(size), arena.alloc(size))
+    else {
+        report_failed("invert_twice", None, 0, "too big for the arena");
+        return;
+    };
+    input.pattern.fill(input_buffer);
+    output_buffer.fill(0);
     let input_buffer_addr = [input_buffer.as_ptr() as u32];
 
     let (mut pio, sm0, sm1, _, _) = pio.split(resets);
@@ -274,21 +467,34 @@ pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
         "       jmp     !osre, more",
     );
 
-    let (sm0, rx0, tx0) = rp2040_hal::pio::PIOBuilder::from_installed_program(
+    let (_sm0, rx0, tx0) = rp2040_hal::pio::PIOBuilder::from_installed_program(
         pio.install(&invert_pio.program).unwrap(),
     )
     .autopull(false)
     .autopush(false)
     .build(sm0);
-    sm0.start();
 
-    let (sm1, rx1, tx1) = rp2040_hal::pio::PIOBuilder::from_installed_program(
+    let (_sm1, rx1, tx1) = rp2040_hal::pio::PIOBuilder::from_installed_program(
         pio.install(&invert_pio_again.program).unwrap(),
     )
     .autopull(false)
     .autopush(false)
     .build(sm1);
-    sm1.start();
+
+    // Start both state machines on the same cycle so the pipeline
+    // begins in lockstep rather than relying on `irq wait 4` alone.
+    let sms = lax_pio::SmGroup::new(lax_pio::PioBlock::Pio0)
+        .with(0)
+        .with(1);
+    let clkdiv = sms.set_clock(0, sys_hz, sys_hz);
+    sms.set_clock(1, sys_hz, sys_hz);
+    sms.restart();
+    sms.start();
+    log::debug!(
+        "sm0 and sm1 running: {}, {} Hz",
+        sms.is_running(),
+        clkdiv.sm_hz(sys_hz)
+    );
 
     let txf0 = tx0.fifo_address();
     let rxf0 = rx0.fifo_address();
@@ -296,8 +502,11 @@ pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
     let txf1 = tx1.fifo_address();
     let rxf1 = rx1.fifo_address();
 
-    log::info!("input_buffer: {:02x?}", input_buffer);
-    log::info!("output_buffer: {:02x?}", output_buffer);
+    log::info!(
+        "*** Running invert_twice on {} bytes of {}",
+        size,
+        input.pattern
+    );
 
     // This DMA channel transfers data from the PIO state machine's
     // RX FIFO to the output buffer. It will be stalled until the
@@ -313,7 +522,7 @@ pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
             address: output_buffer.as_mut_ptr(),
             increment: true,
         },
-        tx_count: SIZE as u32 / 4,
+        tx_count: size as u32 / 4,
         tx_req: TxReq::Pio0Rx1,
         byte_swap: false,
         start: true,
@@ -333,7 +542,7 @@ pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
             address: txf1.cast_mut().cast(),
             increment: false,
         },
-        tx_count: SIZE as u32 / 4,
+        tx_count: size as u32 / 4,
         tx_req: TxReq::Pio0Tx1,
         byte_swap: false,
         start: true,
@@ -353,7 +562,7 @@ pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
             address: txf0.cast_mut().cast(),
             increment: false,
         },
-        tx_count: SIZE as u32 / 4,
+        tx_count: size as u32 / 4,
         tx_req: TxReq::Pio0Tx0,
         byte_swap: false,
         start: false,
@@ -379,18 +588,64 @@ pub fn test_with_pio_invert_twice(pio: PIO0, resets: &mut RESETS) {
     });
 
     // Start the DMA transfers
+    let start = time::time_us64();
     dma0.trigger();
 
     // Wait for the DMA transfers to complete
-    dma0.wait();
-    dma2.wait();
-    dma3.wait();
+    let done = dma0
+        .wait_timeout(DMA_TIMEOUT)
+        .and_then(|()| dma2.wait_timeout(DMA_TIMEOUT))
+        .and_then(|()| dma3.wait_timeout(DMA_TIMEOUT));
+    let elapsed_us = time::time_us64() - start;
+    sms.stop();
+    if done.is_err() {
+        log::error!("!!! invert_twice timed out");
+        dma1.abort();
+        dma2.abort();
+        dma3.abort();
+        report_failed("invert_twice", None, elapsed_us, "timed out");
+        return;
+    }
+
+    hexdump::hexdump(log::Level::Debug, "input_buffer", input_buffer);
+    hexdump::hexdump(log::Level::Debug, "output_buffer", output_buffer);
+
+    expected.copy_from_slice(input_buffer);
+    golden::invert(expected);
+    golden::invert(expected);
+    let mismatches = hexdump::diff(log::Level::Error, "invert_twice", expected, output_buffer);
+    report::result(&report::TestResult::check(
+        "invert_twice",
+        None,
+        elapsed_us,
+        mismatches,
+    ));
 
-    log::info!("input_buffer: {:02x?}", input_buffer);
-    log::info!("output_buffer: {:02x?}", output_buffer);
+    let sm_config = pio_timing::SmConfig {
+        clkdiv: clkdiv.as_tuple(),
+        ..Default::default()
+    };
+    log_benchmark(
+        "invert",
+        &invert_pio.program,
+        &sm_config,
+        sys_hz,
+        size,
+        size,
+        elapsed_us,
+    );
+    log_benchmark(
+        "invert_again",
+        &invert_pio_again.program,
+        &sm_config,
+        sys_hz,
+        size,
+        size,
+        elapsed_us,
+    );
 }
 
-pub fn test_with_pio_expand_12times(pio: PIO0, resets: &mut RESETS) {
+pub fn test_with_pio_expand_12times(pio: PIO0, resets: &mut RESETS, sys_hz: u32) {
     // | DMA Channel | Source (Read Address)      | Destination (Write Address) | FIFO Connection           | Shift Register              |
     // |-------------|----------------------------|-----------------------------|---------------------------|-----------------------------|
     // | DMA 1 (TX)  | RAM Buffer                 | PIO TX FIFO (PIO0_TXF_SM0)  | TX FIFO feeds OSR         | OSR (Output Shift Register) |
@@ -399,6 +654,7 @@ pub fn test_with_pio_expand_12times(pio: PIO0, resets: &mut RESETS) {
     const SIZE: usize = 4;
     let input_buffer = [0x5au8; SIZE];
     let mut output_buffer = [0u8; 12 * SIZE]; // bpp = 1; 12 /bpp
+    let mut expected = [0u8; 12 * SIZE];
 
     let (mut pio, sm0, _, _, _) = pio.split(resets);
 
@@ -426,8 +682,8 @@ pub fn test_with_pio_expand_12times(pio: PIO0, resets: &mut RESETS) {
     let txf = tx.fifo_address();
     let rxf = rx.fifo_address();
 
-    log::info!("input_buffer: {:02x?}", input_buffer);
-    log::info!("output_buffer: {:02x?}", output_buffer);
+    crate::blog!(log::Level::Info, "input_buffer: {:02x?}", input_buffer);
+    crate::blog!(log::Level::Info, "output_buffer: {:02x?}", output_buffer);
 
     // This DMA channel transfers data from the input buffer to the PIO state machine's TX FIFO.
     let dma1 = LaxDmaWrite::new::<dma::CH1>(Config {
@@ -467,60 +723,98 @@ pub fn test_with_pio_expand_12times(pio: PIO0, resets: &mut RESETS) {
     });
 
     // Start the DMA transfers
+    let start = time::time_us64();
     dma1.trigger();
     dma2.trigger();
 
     // Wait for the DMA transfers to complete
-    dma1.wait();
-    dma2.wait();
+    let done = dma1
+        .wait_timeout(DMA_TIMEOUT)
+        .and_then(|()| dma2.wait_timeout(DMA_TIMEOUT));
+    let elapsed_us = time::time_us64() - start;
+    if done.is_err() {
+        log::error!("!!! expand_times12 timed out");
+        dma2.abort();
+        report_failed("expand_times12", None, elapsed_us, "timed out");
+        return;
+    }
+
+    crate::blog!(log::Level::Info, "input_buffer: {:02x?}", input_buffer);
+    hexdump::hexdump(log::Level::Info, "output_buffer", &output_buffer);
 
-    log::info!("input_buffer: {:02x?}", input_buffer);
-    log::info!("output_buffer: {:02x?}", output_buffer);
+    golden::replicate(
+        &input_buffer,
+        1,
+        12,
+        &golden::ShiftConfig::LSB_FIRST,
+        &mut expected,
+    );
+    let mismatches = hexdump::diff(
+        log::Level::Error,
+        "expand_times12",
+        &expected,
+        &output_buffer,
+    );
+    report::result(&report::TestResult::check(
+        "expand_times12",
+        None,
+        elapsed_us,
+        mismatches,
+    ));
+    log_benchmark(
+        "expand_times12",
+        &expand_times12_pio.program,
+        &pio_timing::SmConfig {
+            autopull: true,
+            autopush: true,
+            ..Default::default()
+        },
+        sys_hz,
+        input_buffer.len(),
+        output_buffer.len(),
+        elapsed_us,
+    );
 }
 
 /// Generates a PIO program to produce greyscale color encoded as RGB444
 /// physically. Each pixel may have 2, 4, or 16 greyscale levels (1, 2, or 4 bpp).
-fn greyscale_pio(color: MonochromeColor) -> pio::Program<{ pio::RP2040_MAX_PROGRAM_SIZE }> {
-    let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new();
-
+fn greyscale_pio(color: MonochromeColor) -> pio_programs::Program {
     const RGB_BPP: u8 = 12;
     let bpp = color as u8;
 
-    let mut repeat = a.label();
-
-    // Pull `bpp` bits (1, 2, or 4) from the TX FIFO into OSR
-    a.out(pio::OutDestination::X, bpp);
-
-    // Loop counter in `Y` to repeat `bpp` as many times as need
-    // to fill RGB444 for the greyscale color.
-    a.set(pio::SetDestination::Y, RGB_BPP / bpp - 1);
-    a.bind(&mut repeat);
-    // Push the bits into ISR which goes into RX FIFO.
-    a.r#in(pio::InSource::X, bpp);
-    // Repeat
-    a.jmp(pio::JmpCondition::YDecNonZero, &mut repeat);
-
-    a.assemble_program()
+    pio_programs::ProgramSpec::BitReplicate {
+        bpp,
+        times: RGB_BPP / bpp,
+    }
+    .generate()
+    .unwrap()
 }
 
-pub fn test_with_pio_expand_dynamic(pio: PIO0, resets: &mut RESETS, color: MonochromeColor) {
+/// Streams `input` through `program` running on PIO0 SM0 with autopull and
+/// autopush, and collects the results into `output`. Both buffers must be
+/// whole 32-bit words. Returns the time the DMA transfers took in microseconds.
+fn stream_through_pio(
+    pio: PIO0,
+    resets: &mut RESETS,
+    program: &pio_programs::Program,
+    shift: &golden::ShiftConfig,
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<u64, time::TimedOut> {
     // | DMA Channel | Source (Read Address)      | Destination (Write Address) | FIFO Connection           | Shift Register              |
     // |-------------|----------------------------|-----------------------------|---------------------------|-----------------------------|
     // | DMA 1 (TX)  | RAM Buffer                 | PIO TX FIFO (PIO0_TXF_SM0)  | TX FIFO feeds OSR         | OSR (Output Shift Register) |
     // | DMA 2 (RX)  | PIO RX FIFO (PIO0_RXF_SM0) | RAM Buffer                  | RX FIFO receives from ISR | ISR (Input Shift Register)  |
 
-    const RGB_BPP: u8 = 12;
-    let bpp = RGB_BPP / color as u8;
-
-    const SIZE: usize = 8;
-    let input_buffer: [u8; SIZE] = [0xaa; SIZE];
-    let mut output_buffer: [u8; 12 * SIZE] = [0u8; 12 * SIZE]; // Max output size, each input bit repeated 12 times (greyscale RGB444)
-
     let (mut pio, sm0, _, _, _) = pio.split(resets);
 
-    let greyscale_pio = greyscale_pio(color);
-    let installed_pio = pio.install(&greyscale_pio).unwrap();
+    let installed_pio = pio.install(program).unwrap();
+    // Thresholds of 32 bits are encoded as 0.
     let (sm, rx, tx) = rp2040_hal::pio::PIOBuilder::from_installed_program(installed_pio)
+        .out_shift_direction(shift.out_shift.into())
+        .in_shift_direction(shift.in_shift.into())
+        .pull_threshold(shift.pull_threshold & 0x1f)
+        .push_threshold(shift.push_threshold & 0x1f)
         .autopull(true)
         .autopush(true)
         .build(sm0);
@@ -529,24 +823,21 @@ pub fn test_with_pio_expand_dynamic(pio: PIO0, resets: &mut RESETS, color: Monoc
     let txf = tx.fifo_address();
     let rxf = rx.fifo_address();
 
-    log::info!("input_buffer: {:02x?}", input_buffer);
-    log::info!("output_buffer: {:02x?}", output_buffer);
-
     // This DMA channel transfers data from the input buffer to the PIO state machine's TX FIFO.
     let dma1 = LaxDmaWrite::new::<dma::CH1>(Config {
         high_priority: false,
         word_size: TxSize::_32bit,
         source: Source {
-            address: input_buffer.as_ptr(),
+            address: input.as_ptr(),
             increment: true,
         },
         destination: Destination {
             address: txf.cast_mut().cast(),
             increment: false,
         },
-        tx_count: SIZE as u32 / 4,
+        tx_count: input.len() as u32 / 4,
         tx_req: TxReq::Pio0Tx0,
-        byte_swap: false,
+        byte_swap: shift.byte_swap_in,
         start: false,
     });
 
@@ -560,23 +851,427 @@ pub fn test_with_pio_expand_dynamic(pio: PIO0, resets: &mut RESETS, color: Monoc
             increment: false,
         },
         destination: Destination {
-            address: output_buffer.as_mut_ptr(),
+            address: output.as_mut_ptr(),
             increment: true,
         },
-        tx_count: bpp as u32 * SIZE as u32 / 4,
+        tx_count: output.len() as u32 / 4,
         tx_req: TxReq::Pio0Rx0,
-        byte_swap: false,
+        byte_swap: shift.byte_swap_out,
         start: false,
     });
 
     // Start the DMA transfers
+    let scope = profile::Scope::new("pio_stream");
+    let start = time::time_us64();
     dma1.trigger();
     dma2.trigger();
 
     // Wait for the DMA transfers to complete
-    dma1.wait();
-    dma2.wait();
+    let done = dma1
+        .wait_timeout(DMA_TIMEOUT)
+        .and_then(|()| dma2.wait_timeout(DMA_TIMEOUT));
+    let elapsed_us = time::time_us64() - start;
+    drop(scope);
+    if done.is_err() {
+        log::error!("!!! PIO stream timed out");
+        dma1.abort();
+        dma2.abort();
+    }
+
+    done.map(|()| elapsed_us)
+}
+
+pub fn test_with_pio_expand_dynamic(
+    pio: PIO0,
+    resets: &mut RESETS,
+    color: MonochromeColor,
+    shift: golden::ShiftConfig,
+    input: Input,
+    sys_hz: u32,
+) {
+    arena::with(|arena| expand_dynamic(arena, pio, resets, color, shift, input, sys_hz));
+}
 
-    log::info!("input_buffer: {:02x?}", input_buffer);
-    log::info!("output_buffer: {:02x?}", output_buffer);
+fn expand_dynamic(
+    arena: &mut arena::Arena,
+    pio: PIO0,
+    resets: &mut RESETS,
+    color: MonochromeColor,
+    shift: golden::ShiftConfig,
+    input: Input,
+    sys_hz: u32,
+) {
+    const RGB_BPP: u8 = 12;
+    // Each input bit repeated this many times (greyscale RGB444).
+    let times = RGB_BPP / color as u8;
+    let size = input.size;
+    let output_size = times as usize * size;
+
+    let mut name = LineBuffer::<64>::new();
+    write!(name, "greyscale_{:?}_{}", color, shift.name()).ok();
+    let name = core::str::from_utf8(name.as_bytes()).unwrap_or("greyscale");
+
+    let (Ok(input_buffer), Ok(output_buffer), Ok(expected)) = (
+        arena.alloc(size),
+        arena.alloc(output_size),
+        arena.alloc(output_size),
+    ) else {
+        report_failed(name, None, 0, "too big for the arena");
+        return;
+    };
+    input.pattern.fill(input_buffer);
+    output_buffer.fill(0);
+
+    let greyscale_pio = greyscale_pio(color);
+
+    log::info!(
+        "{:?} {}, {} bytes of {}",
+        color,
+        shift.name(),
+        size,
+        input.pattern
+    );
+
+    let Ok(elapsed_us) = stream_through_pio(
+        pio,
+        resets,
+        &greyscale_pio,
+        &shift,
+        input_buffer,
+        output_buffer,
+    ) else {
+        report_failed(name, None, 0, "timed out");
+        return;
+    };
+
+    hexdump::hexdump(log::Level::Debug, "input_buffer", input_buffer);
+    hexdump::hexdump(log::Level::Debug, "output_buffer", output_buffer);
+
+    golden::replicate(input_buffer, color as u8, times, &shift, expected);
+    let mismatches = hexdump::diff(log::Level::Error, name, expected, output_buffer);
+    report::result(&report::TestResult::check(
+        name, None, elapsed_us, mismatches,
+    ));
+    log_benchmark(
+        "greyscale",
+        &greyscale_pio,
+        &pio_timing::SmConfig {
+            autopull: true,
+            autopush: true,
+            ..Default::default()
+        },
+        sys_hz,
+        size,
+        output_size,
+        elapsed_us,
+    );
+}
+
+/// Runs every program from the generator registry on a counter pattern and
+/// checks its output against `golden::program`.
+pub fn test_with_pio_programs(get_pio: fn() -> PIO0, resets: &mut RESETS, sys_hz: u32) {
+    // Large enough for the packing programs to fill whole output words.
+    const SIZE: usize = 16;
+    const MAX_RATIO: usize = 32;
+    let mut input_buffer = [0u8; SIZE];
+    for (i, b) in input_buffer.iter_mut().enumerate() {
+        *b = 0x10 * i as u8 + 0x0f - i as u8;
+    }
+    let mut output_buffer = [0u8; SIZE * MAX_RATIO];
+    let mut expected = [0u8; SIZE * MAX_RATIO];
+
+    for (name, example, _) in pio_programs::REGISTRY {
+        let spec = match pio_programs::ProgramSpec::parse(example) {
+            Ok(spec) => spec,
+            Err(e) => {
+                log::error!("!!! {}: bad description {:?}: {:?}", name, example, e);
+                report_failed(name, None, 0, "bad description");
+                continue;
+            }
+        };
+        let program = spec.generate().unwrap();
+        let (num, den) = spec.output_ratio();
+        let out_size = SIZE * num as usize / den as usize;
+
+        log::info!("*** Running PIO program {} ({})", spec.name(), example);
+        output_buffer.fill(0);
+        let Ok(elapsed_us) = stream_through_pio(
+            get_pio(),
+            resets,
+            &program,
+            &golden::ShiftConfig::LSB_FIRST,
+            &input_buffer,
+            &mut output_buffer[..out_size],
+        ) else {
+            report_failed(spec.name(), None, 0, "timed out");
+            continue;
+        };
+
+        crate::blog!(log::Level::Info, "input_buffer: {:02x?}", input_buffer);
+        hexdump::hexdump(
+            log::Level::Info,
+            "output_buffer",
+            &output_buffer[..out_size],
+        );
+
+        let expected = &mut expected[..out_size];
+        expected.fill(0);
+        golden::program(
+            &spec,
+            &input_buffer,
+            &golden::ShiftConfig::LSB_FIRST,
+            expected,
+        );
+        let mismatches = hexdump::diff(
+            log::Level::Error,
+            spec.name(),
+            expected,
+            &output_buffer[..out_size],
+        );
+        report::result(&report::TestResult::check(
+            spec.name(),
+            None,
+            elapsed_us,
+            mismatches,
+        ));
+        log_benchmark(
+            spec.name(),
+            &program,
+            &pio_timing::SmConfig {
+                autopull: true,
+                autopush: true,
+                ..Default::default()
+            },
+            sys_hz,
+            SIZE,
+            out_size,
+            elapsed_us,
+        );
+    }
+}
+
+/// Bit `index` of a pixel stream where pixels are packed LSB or MSB first.
+fn stream_bit(buffer: &[u8], index: usize, msb_first: bool) -> bool {
+    let bit = if msb_first { 7 - index % 8 } else { index % 8 };
+    buffer[index / 8] & (1 << bit) != 0
+}
+
+fn set_stream_bits(buffer: &mut [u8], bits: core::ops::Range<usize>, msb_first: bool) {
+    for index in bits {
+        let bit = if msb_first { 7 - index % 8 } else { index % 8 };
+        buffer[index / 8] |= 1 << bit;
+    }
+}
+
+/// Documents where every input pixel ends up in the greyscale output by
+/// lighting one pixel at a time. With a matching `shift` configuration,
+/// pixel `p` of the input stream must become output stream bits
+/// `p * 12..(p + 1) * 12`, counting in the same bit order as the input.
+pub fn test_pixel_order(
+    get_pio: fn() -> PIO0,
+    resets: &mut RESETS,
+    color: MonochromeColor,
+    shift: golden::ShiftConfig,
+) {
+    const RGB_BPP: usize = 12;
+    const SIZE: usize = 4;
+    let bpp = color as usize;
+    let times = RGB_BPP / bpp;
+    let out_size = SIZE * times;
+    let msb_first = shift.out_shift == golden::ShiftDirection::Left;
+
+    log::info!("*** Running pixel order test, {:?} {}", color, shift.name());
+
+    let program = greyscale_pio(color);
+    let start_us = time::time_us64();
+    let mut failures = 0;
+    let mut mismatched_bytes = 0;
+    for pixel in 0..SIZE * 8 / bpp {
+        let mut input_buffer = [0u8; SIZE];
+        set_stream_bits(&mut input_buffer, pixel * bpp..(pixel + 1) * bpp, msb_first);
+        let mut output_buffer = [0u8; SIZE * RGB_BPP];
+        let mut expected = [0u8; SIZE * RGB_BPP];
+
+        if stream_through_pio(
+            get_pio(),
+            resets,
+            &program,
+            &shift,
+            &input_buffer,
+            &mut output_buffer[..out_size],
+        )
+        .is_err()
+        {
+            failures += 1;
+            log::error!("!!! pixel {} timed out", pixel);
+            continue;
+        }
+        golden::replicate(
+            &input_buffer,
+            bpp as u8,
+            times as u8,
+            &shift,
+            &mut expected[..out_size],
+        );
+
+        let lit = (0..out_size * 8).filter(|&i| stream_bit(&output_buffer, i, msb_first));
+        let first = lit.clone().next();
+        let last = lit.clone().next_back();
+        let count = lit.count();
+        log::info!(
+            "pixel {:2} (input {:02x?}) -> output stream bits {:?}..={:?} ({} bits)",
+            pixel,
+            input_buffer,
+            first,
+            last,
+            count
+        );
+
+        let in_place = first == Some(pixel * RGB_BPP)
+            && last == Some((pixel + 1) * RGB_BPP - 1)
+            && count == RGB_BPP;
+        let mismatches = hexdump::diff(
+            log::Level::Error,
+            "pixel",
+            &expected[..out_size],
+            &output_buffer[..out_size],
+        );
+        mismatched_bytes += mismatches;
+        if mismatches != 0 || !in_place {
+            failures += 1;
+            log::error!("!!! pixel {} misplaced", pixel);
+        }
+    }
+
+    let mut name = LineBuffer::<64>::new();
+    write!(name, "pixel_order_{:?}_{}", color, shift.name()).ok();
+    let mut detail = LineBuffer::<64>::new();
+    write!(detail, "{} pixels misplaced", failures).ok();
+    report::result(&report::TestResult {
+        name: core::str::from_utf8(name.as_bytes()).unwrap_or("pixel_order"),
+        channel: None,
+        duration_us: time::time_us64() - start_us,
+        status: if failures == 0 {
+            report::Status::Pass
+        } else {
+            report::Status::Fail
+        },
+        mismatches: mismatched_bytes,
+        detail: if failures == 0 {
+            ""
+        } else {
+            core::str::from_utf8(detail.as_bytes()).unwrap_or_default()
+        },
+    });
+
+    if failures == 0 {
+        log::info!("*** pixel order {:?} {} passed", color, shift.name());
+    } else {
+        log::error!(
+            "!!! pixel order {:?} {} failed for {} pixels",
+            color,
+            shift.name(),
+            failures
+        );
+    }
+}
+
+pub fn test_with_pio_scale(
+    pio: PIO0,
+    resets: &mut RESETS,
+    config: scaler::ScaleConfig,
+    sys_hz: u32,
+) {
+    const MAX_INPUT: usize = 64;
+    const MAX_OUTPUT: usize = 1024;
+    const MAX_LINES: usize = 64;
+
+    log::info!("*** Running scaler test {:?}", config);
+    let mut name = LineBuffer::<64>::new();
+    write!(
+        name,
+        "scaler_{}bpp_{}x{}",
+        config.bpp, config.h_scale, config.v_scale
+    )
+    .ok();
+    let name = core::str::from_utf8(name.as_bytes()).unwrap_or("scaler");
+
+    let mut input_buffer = [0u8; MAX_INPUT];
+    for (i, b) in input_buffer.iter_mut().enumerate() {
+        *b = i as u8 * 0x1d + 0x31;
+    }
+    let mut output_buffer = [0u8; MAX_OUTPUT];
+    let mut expected = [0u8; MAX_OUTPUT];
+    let mut control = [0u32; MAX_LINES];
+
+    let shift = golden::ShiftConfig::LSB_FIRST;
+    let elapsed_us = match scaler::scale(
+        pio,
+        resets,
+        &config,
+        &shift,
+        &input_buffer,
+        &mut control,
+        &mut output_buffer,
+    ) {
+        Ok(elapsed_us) => elapsed_us,
+        Err(e) => {
+            log::error!("!!! scaler {:?} failed: {:?}", config, e);
+            let detail = match e {
+                scaler::ScaleError::TimedOut => "timed out",
+                _ => "failed to start",
+            };
+            report_failed(name, None, 0, detail);
+            return;
+        }
+    };
+    let out_size = config.output_bytes();
+    config.expected(&input_buffer, &shift, &mut expected[..out_size]);
+
+    log::info!(
+        "input_buffer: {:02x?}",
+        &input_buffer[..config.input_bytes()]
+    );
+    hexdump::hexdump(
+        log::Level::Debug,
+        "output_buffer",
+        &output_buffer[..out_size],
+    );
+
+    let mismatches = hexdump::diff(
+        log::Level::Error,
+        "scaler",
+        &expected[..out_size],
+        &output_buffer[..out_size],
+    );
+    report::result(&report::TestResult::check(
+        name, None, elapsed_us, mismatches,
+    ));
+    if mismatches != 0 {
+        log::error!("!!! scaler {:?} failed", config);
+        hexdump::hexdump(log::Level::Error, "expected", &expected[..out_size]);
+        hexdump::hexdump(log::Level::Error, "got", &output_buffer[..out_size]);
+    } else {
+        log::info!("*** scaler {:?} passed", config);
+    }
+
+    let program = pio_programs::ProgramSpec::BitReplicate {
+        bpp: config.bpp,
+        times: config.h_scale,
+    }
+    .generate()
+    .unwrap();
+    log_benchmark(
+        "scaler",
+        &program,
+        &pio_timing::SmConfig {
+            autopull: true,
+            autopush: true,
+            ..Default::default()
+        },
+        sys_hz,
+        config.input_bytes() * config.v_scale as usize,
+        out_size,
+        elapsed_us,
+    );
 }
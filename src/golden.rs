@@ -0,0 +1,384 @@
+//! Software models of the PIO pipelines to check the hardware against.
+//!
+//! The models go through the same steps as the data does: DMA reads
+//! little-endian 32-bit words (optionally byte swapped) into the TX FIFO,
+//! the program shifts bits out of OSR and into ISR in the configured
+//! directions with autopull and autopush at the configured thresholds, and
+//! DMA writes the RX FIFO words back (optionally byte swapped).
+//!
+//! Depends only on `pio_programs`, the host crate compiles this file as
+//! `golden` to run the unit tests below.
+
+use crate::pio_programs::ProgramSpec;
+
+/// Like `rp2040_hal::pio::ShiftDirection`, which the host can't build.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShiftDirection {
+    Left,
+    Right,
+}
+
+#[cfg(target_os = "none")]
+impl From<ShiftDirection> for rp2040_hal::pio::ShiftDirection {
+    fn from(direction: ShiftDirection) -> Self {
+        match direction {
+            ShiftDirection::Left => rp2040_hal::pio::ShiftDirection::Left,
+            ShiftDirection::Right => rp2040_hal::pio::ShiftDirection::Right,
+        }
+    }
+}
+
+/// How pixels travel through the state machine and DMA.
+#[derive(Copy, Clone, Debug)]
+pub struct ShiftConfig {
+    /// Direction OSR shifts on `out`: `Right` takes the LSBs first.
+    pub out_shift: ShiftDirection,
+    /// Direction ISR shifts on `in`: `Right` moves the earliest bits to the LSBs.
+    pub in_shift: ShiftDirection,
+    /// Bits shifted out of OSR before autopull, 1..=32.
+    pub pull_threshold: u8,
+    /// Bits shifted into ISR before autopush, 1..=32.
+    pub push_threshold: u8,
+    /// DMA byte swap when feeding the TX FIFO.
+    pub byte_swap_in: bool,
+    /// DMA byte swap when draining the RX FIFO.
+    pub byte_swap_out: bool,
+}
+
+impl ShiftConfig {
+    /// Pixel 0 is in the least significant bits of byte 0, both in the
+    /// input and in the output. This is the `PIOBuilder` default.
+    pub const LSB_FIRST: ShiftConfig = ShiftConfig {
+        out_shift: ShiftDirection::Right,
+        in_shift: ShiftDirection::Right,
+        pull_threshold: 32,
+        push_threshold: 32,
+        byte_swap_in: false,
+        byte_swap_out: false,
+    };
+
+    /// Pixel 0 is in the most significant bits of byte 0, both in the
+    /// input and in the output, as MSB-first display panels expect.
+    ///
+    /// DMA swaps the bytes so byte 0 lands in the top of the 32-bit word,
+    /// where the left-shifting OSR starts and the left-shifting ISR ends.
+    pub const MSB_FIRST: ShiftConfig = ShiftConfig {
+        out_shift: ShiftDirection::Left,
+        in_shift: ShiftDirection::Left,
+        pull_threshold: 32,
+        push_threshold: 32,
+        byte_swap_in: true,
+        byte_swap_out: true,
+    };
+
+    pub fn name(&self) -> &'static str {
+        match (self.out_shift, self.in_shift) {
+            (ShiftDirection::Right, ShiftDirection::Right) => "lsb_first",
+            (ShiftDirection::Left, ShiftDirection::Left) => "msb_first",
+            _ => "mixed",
+        }
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+fn threshold(bits: u8) -> u32 {
+    match bits {
+        0 => 32,
+        n => n.min(32) as u32,
+    }
+}
+
+/// Model of OSR fed from `input` by DMA and autopull.
+struct Osr<'a> {
+    words: core::slice::Iter<'a, [u8; 4]>,
+    shift: ShiftConfig,
+    osr: u32,
+    count: u32,
+}
+
+impl<'a> Osr<'a> {
+    fn new(input: &'a [u8], shift: &ShiftConfig) -> Self {
+        Osr {
+            words: input.as_chunks().0.iter(),
+            shift: *shift,
+            osr: 0,
+            count: 32,
+        }
+    }
+
+    /// `out` of `bits` bits, `None` once the input is exhausted.
+    fn out(&mut self, bits: u32) -> Option<u32> {
+        if self.count >= threshold(self.shift.pull_threshold) {
+            let word = u32::from_le_bytes(*self.words.next()?);
+            self.osr = if self.shift.byte_swap_in {
+                word.swap_bytes()
+            } else {
+                word
+            };
+            self.count = 0;
+        }
+
+        let value = match self.shift.out_shift {
+            ShiftDirection::Right => {
+                let value = self.osr & mask(bits);
+                self.osr = self.osr.checked_shr(bits).unwrap_or(0);
+                value
+            }
+            ShiftDirection::Left => {
+                let value = self.osr.checked_shr(32 - bits).unwrap_or(0) & mask(bits);
+                self.osr = self.osr.checked_shl(bits).unwrap_or(0);
+                value
+            }
+        };
+        self.count += bits;
+
+        Some(value)
+    }
+}
+
+/// Model of ISR drained into `output` by autopush and DMA.
+struct Isr<'a> {
+    output: &'a mut [u8],
+    written: usize,
+    shift: ShiftConfig,
+    isr: u32,
+    count: u32,
+}
+
+impl<'a> Isr<'a> {
+    fn new(output: &'a mut [u8], shift: &ShiftConfig) -> Self {
+        Isr {
+            output,
+            written: 0,
+            shift: *shift,
+            isr: 0,
+            count: 0,
+        }
+    }
+
+    /// `in` of `bits` bits, `false` once the output is full.
+    fn push_bits(&mut self, value: u32, bits: u32) -> bool {
+        let value = value & mask(bits);
+        self.isr = match self.shift.in_shift {
+            ShiftDirection::Right => {
+                self.isr.checked_shr(bits).unwrap_or(0) | value.checked_shl(32 - bits).unwrap_or(0)
+            }
+            ShiftDirection::Left => self.isr.checked_shl(bits).unwrap_or(0) | value,
+        };
+        self.count += bits;
+
+        if self.count >= threshold(self.shift.push_threshold) {
+            let word = if self.shift.byte_swap_out {
+                self.isr.swap_bytes()
+            } else {
+                self.isr
+            };
+            let Some(dst) = self.output.get_mut(self.written..self.written + 4) else {
+                return false;
+            };
+            dst.copy_from_slice(&word.to_le_bytes());
+            self.written += 4;
+            self.isr = 0;
+            self.count = 0;
+        }
+
+        self.written < self.output.len()
+    }
+}
+
+/// Expected output of the `replicate` program: every `bpp`-bit pixel of
+/// `input` repeated `times` times. Returns the number of bytes produced.
+pub fn replicate(
+    input: &[u8],
+    bpp: u8,
+    times: u8,
+    shift: &ShiftConfig,
+    output: &mut [u8],
+) -> usize {
+    let mut osr = Osr::new(input, shift);
+    let mut isr = Isr::new(output, shift);
+
+    'outer: while let Some(pixel) = osr.out(bpp as u32) {
+        for _ in 0..times {
+            if !isr.push_bits(pixel, bpp as u32) {
+                break 'outer;
+            }
+        }
+    }
+
+    isr.written
+}
+
+/// One `mov x, ~x` stage, in place. Two of them give back the input.
+pub fn invert(data: &mut [u8]) {
+    data.iter_mut().for_each(|b| *b = !*b);
+}
+
+/// Expected output of a generated program, see `ProgramSpec::generate`
+/// for the instructions each pass through the program runs. Returns the
+/// number of bytes produced.
+pub fn program(spec: &ProgramSpec, input: &[u8], shift: &ShiftConfig, output: &mut [u8]) -> usize {
+    if let ProgramSpec::BitReplicate { bpp, times } = *spec {
+        return replicate(input, bpp, times, shift, output);
+    }
+
+    let mut osr = Osr::new(input, shift);
+    let mut isr = Isr::new(output, shift);
+
+    // `None` once the input runs out, `Some(false)` once the output is full.
+    let mut pass = || -> Option<bool> {
+        let more = match *spec {
+            ProgramSpec::BitReplicate { .. } => unreachable!(),
+            ProgramSpec::Invert => isr.push_bits(!osr.out(32)?, 32),
+            ProgramSpec::BitReverse => isr.push_bits(osr.out(32)?.reverse_bits(), 32),
+            ProgramSpec::ShiftPack { bits } => {
+                let more = isr.push_bits(osr.out(bits as u32)?, bits as u32);
+                osr.out(32 - bits as u32);
+                more
+            }
+            ProgramSpec::NibbleSwap => {
+                let (x, y) = (osr.out(4)?, osr.out(4)?);
+                isr.push_bits(y, 4) && isr.push_bits(x, 4)
+            }
+            ProgramSpec::ByteLaneExtract { lane } => {
+                if lane > 0 {
+                    osr.out(8 * lane as u32)?;
+                }
+                let more = isr.push_bits(osr.out(8)?, 8);
+                if lane < 3 {
+                    osr.out(8 * (3 - lane) as u32);
+                }
+                more
+            }
+        };
+        Some(more)
+    };
+    while pass() == Some(true) {}
+
+    isr.written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replicated(input: &[u8], bpp: u8, times: u8, shift: &ShiftConfig) -> Vec<u8> {
+        let mut output = vec![0; input.len() * times as usize];
+        let written = replicate(input, bpp, times, shift, &mut output);
+        assert_eq!(written, output.len());
+        output
+    }
+
+    fn run(spec: ProgramSpec, input: &[u8]) -> Vec<u8> {
+        let (num, den) = spec.output_ratio();
+        let mut output = vec![0; input.len() * num as usize / den as usize];
+        let written = program(&spec, input, &ShiftConfig::LSB_FIRST, &mut output);
+        assert_eq!(written, output.len());
+        output
+    }
+
+    #[test]
+    fn replicate_lsb_first() {
+        let lsb = &ShiftConfig::LSB_FIRST;
+        // Pixels 0, 2, 15 and 16 set, pixel 16 starts the second word out.
+        assert_eq!(
+            replicated(&[0x05, 0x80, 0x01, 0x00], 1, 2, lsb),
+            [0x33, 0x00, 0x00, 0xc0, 0x03, 0x00, 0x00, 0x00]
+        );
+        // Pixels 0, 1, 2, 3.
+        assert_eq!(
+            replicated(&[0xe4, 0x00, 0x00, 0x00], 2, 2, lsb),
+            [0x50, 0xfa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        // Pixels 1, 2, 3, 4.
+        assert_eq!(
+            replicated(&[0x21, 0x43, 0x00, 0x00], 4, 3, lsb),
+            [0x11, 0x21, 0x22, 0x33, 0x43, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn replicate_msb_first() {
+        let msb = &ShiftConfig::MSB_FIRST;
+        // Pixels 0, 2, 15 and 16 set.
+        assert_eq!(
+            replicated(&[0xa0, 0x01, 0x80, 0x00], 1, 2, msb),
+            [0xcc, 0x00, 0x00, 0x03, 0xc0, 0x00, 0x00, 0x00]
+        );
+        // Pixels 3, 2, 1, 0.
+        assert_eq!(
+            replicated(&[0xe4, 0x00, 0x00, 0x00], 2, 2, msb),
+            [0xfa, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        // Pixels 1, 2, 3, 4.
+        assert_eq!(
+            replicated(&[0x12, 0x34, 0x00, 0x00], 4, 3, msb),
+            [0x11, 0x12, 0x22, 0x33, 0x34, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn replicate_stops_at_the_end() {
+        // Only whole words are written, like autopush and DMA do.
+        let mut output = [0u8; 6];
+        let written = replicate(&[0xff; 4], 1, 2, &ShiftConfig::LSB_FIRST, &mut output);
+        assert_eq!(written, 4);
+        assert_eq!(output, [0xff, 0xff, 0xff, 0xff, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn invert_twice() {
+        let input = [0x00, 0x0f, 0xa5, 0xff];
+        let mut data = input;
+        invert(&mut data);
+        assert_eq!(data, [0xff, 0xf0, 0x5a, 0x00]);
+        invert(&mut data);
+        assert_eq!(data, input);
+    }
+
+    #[test]
+    fn programs() {
+        let word = [0x12, 0x34, 0x56, 0x78];
+        assert_eq!(run(ProgramSpec::Invert, &word), [0xed, 0xcb, 0xa9, 0x87]);
+        assert_eq!(
+            run(ProgramSpec::BitReverse, &word),
+            [0x1e, 0x6a, 0x2c, 0x48]
+        );
+        assert_eq!(
+            run(ProgramSpec::NibbleSwap, &word),
+            [0x21, 0x43, 0x65, 0x87]
+        );
+
+        let words: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        assert_eq!(
+            run(ProgramSpec::ByteLaneExtract { lane: 1 }, &words),
+            [0x11, 0x55, 0x99, 0xdd]
+        );
+        assert_eq!(
+            run(ProgramSpec::ByteLaneExtract { lane: 3 }, &words),
+            [0x33, 0x77, 0xbb, 0xff]
+        );
+
+        // Low nibbles 1 to 8, the rest of each word is dropped.
+        let words: Vec<u8> = (1..=8u32)
+            .flat_map(|i| (0xabcd_ef00 | i).to_le_bytes())
+            .collect();
+        assert_eq!(
+            run(ProgramSpec::ShiftPack { bits: 4 }, &words),
+            [0x21, 0x43, 0x65, 0x87]
+        );
+
+        let spec = ProgramSpec::BitReplicate { bpp: 2, times: 2 };
+        assert_eq!(
+            run(spec, &[0xe4, 0x00, 0x00, 0x00]),
+            replicated(&[0xe4, 0x00, 0x00, 0x00], 2, 2, &ShiftConfig::LSB_FIRST)
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! The watchdog around each experiment, so one that hangs, e.g. waiting on
+//! a DMA channel that never completes, resets the chip instead of the run.
+//!
+//! `registry::run` arms the watchdog before an experiment and disarms it
+//! after. Every reported result feeds it, so an experiment only has to
+//! finish a test every `TIMEOUT`. So do the blocking log sinks while they
+//! wait for the UART, and loops that run many tests for one result.
+//!
+//! Watchdog scratch 2 and 3 hold where the running experiment is in its
+//! list (0 and 1 are the crash record's, the boot ROM looks at 4 to 7),
+//! the next boot reports it as failed and goes on with the experiments
+//! after it.
+
+use rp2040_hal::watchdog::ScratchRegister;
+use rp2040_hal::watchdog::Watchdog;
+
+/// Longest time between results.
+pub const TIMEOUT: fugit::MicrosDurationU32 = fugit::MicrosDurationU32::millis(1000);
+
+/// In the top half of watchdog scratch 2 while an experiment runs.
+const RUNNING_MAGIC: u32 = 0x6775 << 16;
+
+fn watchdog() -> &'static rp2040_pac::watchdog::RegisterBlock {
+    unsafe { &*rp2040_pac::WATCHDOG::PTR }
+}
+
+/// An experiment in a list of them, see `registry::run`.
+#[derive(Copy, Clone, Debug)]
+pub struct Position {
+    /// Hash of the list.
+    list: u16,
+    /// Counting every experiment the list selects.
+    pub step: u16,
+    /// Into `registry::EXPERIMENTS`.
+    pub index: u16,
+}
+
+/// FNV-1a, folded to 16 bits.
+fn hash(list: &str) -> u16 {
+    let hash = list.bytes().fold(0x811c_9dc5u32, |h, b| {
+        (h ^ b as u32).wrapping_mul(0x0100_0193)
+    });
+    (hash >> 16 ^ hash) as u16
+}
+
+impl Position {
+    pub fn new(list: &str, step: usize, index: usize) -> Self {
+        Position {
+            list: hash(list),
+            step: step as u16,
+            index: index as u16,
+        }
+    }
+
+    /// Whether it's in `list`, as far as the hash can tell.
+    pub fn is_in(&self, list: &str) -> bool {
+        self.list == hash(list)
+    }
+}
+
+/// Start the watchdog for the experiment at `at`.
+pub fn arm(watchdog: &mut Watchdog, at: Position) {
+    watchdog.write_scratch(ScratchRegister::Scratch2, RUNNING_MAGIC | at.list as u32);
+    watchdog.write_scratch(
+        ScratchRegister::Scratch3,
+        (at.step as u32) << 16 | at.index as u32,
+    );
+    watchdog.start(TIMEOUT);
+}
+
+/// The experiment finished.
+pub fn disarm(watchdog: &mut Watchdog) {
+    watchdog.disable();
+    watchdog.write_scratch(ScratchRegister::Scratch2, 0);
+}
+
+/// Another `TIMEOUT` to go. Doesn't need the `Watchdog`, so tests deep in
+/// the experiments can call it.
+pub fn feed() {
+    // `Watchdog::start` loads twice the microseconds, see RP2040-E1.
+    watchdog()
+        .load()
+        .write(|w| unsafe { w.bits(TIMEOUT.to_micros() * 2) });
+}
+
+/// The experiment the previous boot was running when the watchdog reset
+/// the chip, with what happened to it, and forget it.
+pub fn take_previous(watchdog: &mut Watchdog) -> Option<(Position, &'static str)> {
+    let running = watchdog.read_scratch(ScratchRegister::Scratch2);
+    if running & 0xffff_0000 != RUNNING_MAGIC {
+        return None;
+    }
+    let place = watchdog.read_scratch(ScratchRegister::Scratch3);
+    watchdog.write_scratch(ScratchRegister::Scratch2, 0);
+
+    // Any other reset leaves the magic behind too, e.g. from a debugger.
+    let reason = self::watchdog().reason().read();
+    let what = if reason.timer().bit_is_set() {
+        "hung, reset by the watchdog"
+    } else if reason.force().bit_is_set() {
+        "panicked"
+    } else {
+        return None;
+    };
+
+    Some((
+        Position {
+            list: running as u16,
+            step: (place >> 16) as u16,
+            index: place as u16,
+        },
+        what,
+    ))
+}
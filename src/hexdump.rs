@@ -0,0 +1,121 @@
+//! Buffer dumps and comparisons that stay readable past a few dozen bytes.
+
+use core::fmt;
+use core::fmt::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Mismatching words `diff` lists, the rest are only counted.
+const MAX_REPORTED: usize = 8;
+
+/// One `hexdump` line: address, hex bytes and ASCII.
+struct HexLine<'a> {
+    address: usize,
+    bytes: &'a [u8],
+}
+
+impl fmt::Display for HexLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}:", self.address)?;
+        for i in 0..BYTES_PER_LINE {
+            if i == BYTES_PER_LINE / 2 {
+                f.write_char(' ')?;
+            }
+            match self.bytes.get(i) {
+                Some(b) => write!(f, " {:02x}", b)?,
+                None => f.write_str("   ")?,
+            }
+        }
+        f.write_str("  |")?;
+        for &b in self.bytes {
+            let printable = b.is_ascii_graphic() || b == b' ';
+            f.write_char(if printable { b as char } else { '.' })?;
+        }
+        f.write_char('|')
+    }
+}
+
+/// Log `bytes` 16 to a line with their addresses and an ASCII column.
+pub fn hexdump(level: log::Level, label: &str, bytes: &[u8]) {
+    log::log!(level, "{}: {} bytes", label, bytes.len());
+    for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = bytes.as_ptr() as usize + i * BYTES_PER_LINE;
+        log::log!(
+            level,
+            "{}",
+            HexLine {
+                address,
+                bytes: line
+            }
+        );
+    }
+}
+
+/// Little-endian word at `offset`, zero-padded past the end.
+fn word_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0u8; 4];
+    for (i, b) in word.iter_mut().enumerate() {
+        *b = bytes.get(offset + i).copied().unwrap_or(0);
+    }
+    u32::from_le_bytes(word)
+}
+
+/// Compare `actual` to `expected`. If they differ, log how many bytes do,
+/// the first offset, and the first few mismatching 32-bit words. Returns
+/// the number of mismatching bytes, a length difference counts too.
+pub fn diff(level: log::Level, label: &str, expected: &[u8], actual: &[u8]) -> usize {
+    let common = expected.len().min(actual.len());
+    let mismatches = expected.iter().zip(actual).filter(|(e, a)| e != a).count()
+        + expected.len().abs_diff(actual.len());
+    if mismatches == 0 {
+        return 0;
+    }
+
+    let first = expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .unwrap_or(common);
+    log::log!(
+        level,
+        "{}: {} of {} bytes differ, first at offset {:#x}",
+        label,
+        mismatches,
+        expected.len().max(actual.len()),
+        first
+    );
+    if expected.len() != actual.len() {
+        log::log!(
+            level,
+            "{}: expected {} bytes, got {}",
+            label,
+            expected.len(),
+            actual.len()
+        );
+    }
+
+    let mut words = (0..common)
+        .step_by(4)
+        .map(|offset| {
+            let e = word_at(&expected[..common], offset);
+            (offset, e, word_at(&actual[..common], offset))
+        })
+        .filter(|(_, e, a)| e != a);
+    for (offset, e, a) in words.by_ref().take(MAX_REPORTED) {
+        log::log!(
+            level,
+            "{}: +{:04x}: expected {:08x} got {:08x} (xor {:08x})",
+            label,
+            offset,
+            e,
+            a,
+            e ^ a
+        );
+    }
+    let more = words.count();
+    if more != 0 {
+        log::log!(level, "{}: and {} more mismatching words", label, more);
+    }
+
+    mismatches
+}
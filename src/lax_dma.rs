@@ -1,7 +1,14 @@
 //! Very unsafe DMA driver for experimental purposes.
 
+use crate::time;
 use rp2040_hal::dma;
 
+/// The number of channels.
+pub const CHANNELS: u8 = 12;
+
+/// How long dropping a channel waits for it to finish before aborting it.
+const DROP_TIMEOUT: time::Duration = time::Duration::millis(100);
+
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
 #[repr(u8)]
@@ -160,7 +167,17 @@ impl LaxDmaWrite {
     pub fn new_chained<CHID: dma::ChannelIndex, CHIDCHAIN: dma::ChannelIndex>(
         config: Config,
     ) -> Self {
-        let ch = unsafe { (*rp2040_pac::DMA::PTR).ch(CHID::id() as usize) };
+        LaxDmaWrite::new_on(CHID::id(), CHIDCHAIN::id(), config)
+    }
+
+    /// Like `new_chained`, with the channels picked at runtime. Chaining a
+    /// channel to itself means no chaining.
+    pub fn new_on(ch_id: u8, ch_id_chain: u8, config: Config) -> Self {
+        assert!(
+            ch_id < CHANNELS && ch_id_chain < CHANNELS,
+            "Invalid DMA channel"
+        );
+        let ch = unsafe { (*rp2040_pac::DMA::PTR).ch(ch_id as usize) };
 
         let (src, src_incr) = (config.source.address, config.source.increment);
         let (dest, dest_incr) = (config.destination.address, config.destination.increment);
@@ -175,7 +192,7 @@ impl LaxDmaWrite {
             w.incr_write().bit(dest_incr);
             w.treq_sel().bits(config.tx_req as u8);
             w.bswap().bit(config.byte_swap);
-            w.chain_to().bits(CHIDCHAIN::id());
+            w.chain_to().bits(ch_id_chain);
             w.high_priority().bit(config.high_priority);
             w.en().bit(true);
             w
@@ -191,12 +208,21 @@ impl LaxDmaWrite {
         }
 
         Self {
-            ch_id: CHID::id(),
-            ch_id_chain: CHIDCHAIN::id(),
+            ch_id,
+            ch_id_chain,
             ch,
         }
     }
 
+    /// Wrap the write address around a `1 << size_bits` byte buffer, which
+    /// must be aligned to its size. Set before triggering.
+    pub fn set_write_ring(&self, size_bits: u8) {
+        self.ch.ch_al1_ctrl().modify(|_, w| unsafe {
+            w.ring_sel().set_bit();
+            w.ring_size().bits(size_bits)
+        });
+    }
+
     pub fn trigger(&self) {
         let channel_flags = 1 << self.ch_id | 1 << self.ch_id_chain;
         unsafe { &*rp2040_pac::DMA::ptr() }
@@ -204,10 +230,29 @@ impl LaxDmaWrite {
             .write(|w| unsafe { w.bits(channel_flags) });
     }
 
+    /// Start only this channel, the one it's chained to starts when it
+    /// finishes.
+    pub fn trigger_only(&self) {
+        unsafe { &*rp2040_pac::DMA::ptr() }
+            .multi_chan_trigger()
+            .write(|w| unsafe { w.bits(1 << self.ch_id) });
+    }
+
+    /// Chain to itself, i.e. to nothing. Do it before aborting a channel
+    /// that's chained to or from others: an abort can trigger the channel
+    /// it chains to (RP2040-E13).
+    pub fn unchain(&self) {
+        self.ch
+            .ch_al1_ctrl()
+            .modify(|_, w| unsafe { w.chain_to().bits(self.ch_id) });
+    }
+
     pub fn is_done(&self) -> bool {
         !self.ch.ch_al1_ctrl().read().busy().bit_is_set()
     }
 
+    /// Unbounded, prefer `wait_timeout`.
+    #[allow(dead_code)]
     pub fn wait(&self) {
         while !self.is_done() {}
 
@@ -215,6 +260,32 @@ impl LaxDmaWrite {
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
     }
 
+    /// Like `wait`, but give up after `timeout`, e.g. when the other end
+    /// never drains or fills its FIFO. The busy channels are logged while
+    /// they still are, then this one is unchained and aborted, so dropping
+    /// it doesn't wait forever.
+    pub fn wait_timeout(&self, timeout: time::Duration) -> Result<(), time::TimedOut> {
+        if let Err(e) = time::Deadline::after(timeout).wait_for(|| self.is_done()) {
+            log::error!("DMA {} timed out", self.ch_id);
+            log_active_channels(log::Level::Error);
+            self.unchain();
+            self.abort();
+            return Err(e);
+        }
+
+        cortex_m::asm::dsb();
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Stop the channel mid-transfer.
+    pub fn abort(&self) {
+        let dma = unsafe { &*rp2040_pac::DMA::ptr() };
+        dma.chan_abort()
+            .write(|w| unsafe { w.bits(1 << self.ch_id) });
+        while dma.chan_abort().read().bits() & 1 << self.ch_id != 0 {}
+    }
+
     pub fn read_error(&self) -> bool {
         self.ch.ch_al1_ctrl().read().read_error().bit_is_set()
     }
@@ -242,7 +313,51 @@ impl LaxDmaWrite {
 
 impl Drop for LaxDmaWrite {
     fn drop(&mut self) {
-        self.wait();
+        // A channel stuck on its DREQ would keep `wait` spinning.
+        self.wait_timeout(DROP_TIMEOUT).ok();
         self.ch.ch_al1_ctrl().reset();
     }
 }
+
+/// Log the registers of channel `i`.
+pub fn log_channel(level: log::Level, i: usize) {
+    let ch = unsafe { (*rp2040_pac::DMA::PTR).ch(i) };
+    let ctrl = ch.ch_al1_ctrl().read();
+    log::log!(
+        level,
+        "DMA {}: ctrl {:08x} read {:08x} write {:08x} count {} treq {} chain {}{}{}{}",
+        i,
+        ctrl.bits(),
+        ch.ch_read_addr().read().bits(),
+        ch.ch_write_addr().read().bits(),
+        ch.ch_trans_count().read().bits(),
+        ctrl.treq_sel().bits(),
+        ctrl.chain_to().bits(),
+        if ctrl.busy().bit_is_set() {
+            " busy"
+        } else {
+            ""
+        },
+        if ctrl.read_error().bit_is_set() {
+            " read error"
+        } else {
+            ""
+        },
+        if ctrl.write_error().bit_is_set() {
+            " write error"
+        } else {
+            ""
+        },
+    );
+}
+
+/// Log the registers of the channels that are busy or stopped on a bus error.
+pub fn log_active_channels(level: log::Level) {
+    let dma = unsafe { &*rp2040_pac::DMA::PTR };
+    for i in 0..CHANNELS as usize {
+        let ctrl = dma.ch(i).ch_al1_ctrl().read();
+        if ctrl.busy().bit_is_set() || ctrl.ahb_error().bit_is_set() {
+            log_channel(level, i);
+        }
+    }
+}
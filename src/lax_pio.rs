@@ -0,0 +1,163 @@
+//! Very unsafe PIO state machine control for experimental purposes.
+//!
+//! Groups state machines of one PIO block so that they are started, stopped
+//! and restarted with a single write to the CTRL register, and so that their
+//! clock dividers tick in lockstep.
+
+use crate::pio_timing;
+
+#[derive(Copy, Clone)]
+pub enum PioBlock {
+    Pio0,
+    Pio1,
+}
+
+impl PioBlock {
+    fn regs(self) -> &'static rp2040_pac::pio0::RegisterBlock {
+        match self {
+            PioBlock::Pio0 => unsafe { &*rp2040_pac::PIO0::PTR },
+            PioBlock::Pio1 => unsafe { &*rp2040_pac::PIO1::PTR },
+        }
+    }
+
+    /// Log the block's FIFO and debug state and the registers of every
+    /// state machine.
+    pub fn log_state(self, level: log::Level) {
+        let regs = self.regs();
+        log::log!(
+            level,
+            "PIO{}: ctrl {:08x} fstat {:08x} fdebug {:08x} flevel {:08x} irq {:02x}",
+            self as u8,
+            regs.ctrl().read().bits(),
+            regs.fstat().read().bits(),
+            regs.fdebug().read().bits(),
+            regs.flevel().read().bits(),
+            regs.irq().read().bits(),
+        );
+        for i in 0..4 {
+            let sm = regs.sm(i);
+            log::log!(
+                level,
+                "PIO{} SM{}: pc {:02x} instr {:04x} clkdiv {:08x} exec {:08x} shift {:08x} pins {:08x}",
+                self as u8,
+                i,
+                sm.sm_addr().read().bits(),
+                sm.sm_instr().read().bits(),
+                sm.sm_clkdiv().read().bits(),
+                sm.sm_execctrl().read().bits(),
+                sm.sm_shiftctrl().read().bits(),
+                sm.sm_pinctrl().read().bits(),
+            );
+        }
+    }
+}
+
+/// State machine clock divider: `int + frac / 256`, `int == 0` means 65536.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClockDivider {
+    pub int: u16,
+    pub frac: u8,
+}
+
+impl ClockDivider {
+    pub const ONE: ClockDivider = ClockDivider { int: 1, frac: 0 };
+
+    /// The divider that runs a state machine closest to `target_hz`,
+    /// clamped to what the hardware can do.
+    pub fn from_target(sys_hz: u32, target_hz: u32) -> Self {
+        if target_hz >= sys_hz {
+            return Self::ONE;
+        }
+
+        // In 1/256ths, rounded to nearest.
+        let div = ((sys_hz as u64) * 256 + target_hz as u64 / 2) / target_hz.max(1) as u64;
+        let div = div.clamp(256, 0xff_ffff);
+        ClockDivider {
+            int: (div >> 8) as u16,
+            frac: div as u8,
+        }
+    }
+
+    pub fn as_tuple(self) -> (u16, u8) {
+        (self.int, self.frac)
+    }
+
+    pub fn sm_hz(self, sys_hz: u32) -> u32 {
+        pio_timing::sm_clock_hz(sys_hz, self.as_tuple())
+    }
+}
+
+/// A set of state machines of one PIO block controlled together.
+#[derive(Copy, Clone)]
+pub struct SmGroup {
+    block: PioBlock,
+    mask: u8,
+}
+
+impl SmGroup {
+    pub const fn new(block: PioBlock) -> Self {
+        SmGroup { block, mask: 0 }
+    }
+
+    pub fn with(self, sm: u8) -> Self {
+        assert!(sm < 4, "Invalid state machine index");
+        SmGroup {
+            mask: self.mask | 1 << sm,
+            ..self
+        }
+    }
+
+    /// Set the clock divider of one state machine to get as close to
+    /// `target_hz` as possible. Returns the divider used.
+    pub fn set_clock(&self, sm: u8, sys_hz: u32, target_hz: u32) -> ClockDivider {
+        let div = ClockDivider::from_target(sys_hz, target_hz);
+        self.set_clock_divider(sm, div);
+        div
+    }
+
+    pub fn set_clock_divider(&self, sm: u8, div: ClockDivider) {
+        assert!(self.mask & 1 << sm != 0, "State machine not in the group");
+        self.block
+            .regs()
+            .sm(sm as usize)
+            .sm_clkdiv()
+            .write(|w| unsafe {
+                w.int().bits(div.int);
+                w.frac().bits(div.frac)
+            });
+    }
+
+    /// Enable all state machines in the group on the same cycle, with
+    /// their clock dividers restarted so they stay in phase.
+    pub fn start(&self) {
+        cortex_m::asm::dsb();
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+
+        self.block.regs().ctrl().modify(|r, w| unsafe {
+            w.sm_enable().bits(r.sm_enable().bits() | self.mask);
+            w.clkdiv_restart().bits(self.mask)
+        });
+    }
+
+    /// Disable all state machines in the group on the same cycle.
+    pub fn stop(&self) {
+        self.block
+            .regs()
+            .ctrl()
+            .modify(|r, w| unsafe { w.sm_enable().bits(r.sm_enable().bits() & !self.mask) });
+    }
+
+    /// Clear the internal state (shift counters, delays, stalls) and the
+    /// clock divider phase of all state machines in the group. The program
+    /// counter and the FIFOs are left alone.
+    pub fn restart(&self) {
+        self.block.regs().ctrl().modify(|_, w| unsafe {
+            w.sm_restart().bits(self.mask);
+            w.clkdiv_restart().bits(self.mask)
+        });
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.block.regs().ctrl().read().sm_enable().bits() & self.mask == self.mask
+    }
+}
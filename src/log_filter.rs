@@ -0,0 +1,124 @@
+//! Per-module log level filtering.
+//!
+//! A filter is described like for `env_logger`: comma-separated
+//! `module::path=level` rules and an optional bare `level` for everything
+//! else, e.g. `info,pico_pio_dma_test::lax_dma=debug`. The longest
+//! matching module path prefix wins.
+//!
+//! The filter used at boot can be compiled in with the `PICO_LOG`
+//! environment variable:
+//!
+//! ```sh
+//! PICO_LOG="debug,pico_pio_dma_test::experiments=info" cargo run --release
+//! ```
+
+use log::LevelFilter;
+
+const MAX_RULES: usize = 8;
+const MAX_PREFIX: usize = 64;
+
+/// The filter compiled in with `PICO_LOG`, everything if not set.
+pub const BUILD_SPEC: &str = match option_env!("PICO_LOG") {
+    Some(spec) => spec,
+    None => "trace",
+};
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    TooManyRules,
+    PrefixTooLong,
+    BadLevel,
+}
+
+#[derive(Copy, Clone)]
+struct Rule {
+    prefix: [u8; MAX_PREFIX],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl Rule {
+    fn prefix(&self) -> &[u8] {
+        &self.prefix[..self.len]
+    }
+}
+
+#[derive(Clone)]
+pub struct Filter {
+    default: LevelFilter,
+    rules: [Option<Rule>; MAX_RULES],
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, FilterError> {
+    s.trim().parse().map_err(|_| FilterError::BadLevel)
+}
+
+impl Filter {
+    /// Let everything through.
+    pub const fn new() -> Self {
+        Filter {
+            default: LevelFilter::Trace,
+            rules: [None; MAX_RULES],
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, FilterError> {
+        let mut filter = Filter {
+            default: LevelFilter::Trace,
+            rules: [None; MAX_RULES],
+        };
+        let mut rules = filter.rules.iter_mut();
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let Some((prefix, level)) = directive.split_once('=') else {
+                filter.default = parse_level(directive)?;
+                continue;
+            };
+
+            let prefix = prefix.trim().as_bytes();
+            if prefix.len() > MAX_PREFIX {
+                return Err(FilterError::PrefixTooLong);
+            }
+            let mut rule = Rule {
+                prefix: [0; MAX_PREFIX],
+                len: prefix.len(),
+                level: parse_level(level)?,
+            };
+            rule.prefix[..prefix.len()].copy_from_slice(prefix);
+            *rules.next().ok_or(FilterError::TooManyRules)? = Some(rule);
+        }
+
+        Ok(filter)
+    }
+
+    /// The most verbose level of the records `module` may log.
+    pub fn level_for(&self, module: &str) -> LevelFilter {
+        self.rules
+            .iter()
+            .flatten()
+            .filter(|r| module.as_bytes().starts_with(r.prefix()))
+            .max_by_key(|r| r.len)
+            .map_or(self.default, |r| r.level)
+    }
+
+    /// The most verbose level any module may log at, for `log::set_max_level`.
+    pub fn max_level(&self) -> LevelFilter {
+        self.rules
+            .iter()
+            .flatten()
+            .map(|r| r.level)
+            .fold(self.default, Ord::max)
+    }
+
+    pub fn log(&self) {
+        log::info!("log filter: default {}", self.default);
+        for rule in self.rules.iter().flatten() {
+            log::info!(
+                "log filter: {}={}",
+                core::str::from_utf8(rule.prefix()).unwrap_or_default(),
+                rule.level
+            );
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Log line layout.
+//!
+//! A format is described by a comma-separated list of the fields to print
+//! in front of the message, e.g. `secs,core,level,module,color`:
+//!
+//! - `hex`: microseconds since boot, 8 hex digits
+//! - `secs`: seconds since boot, `12.345678`
+//! - `delta`: seconds since the previous record, `+0.000123`
+//! - `core`: the core that logged it
+//! - `level`, `module`, `file` (`file@line`)
+//! - `color`: colour by level with VT escapes
+//!
+//! The format used at boot can be compiled in with the `PICO_LOG_FORMAT`
+//! environment variable:
+//!
+//! ```sh
+//! PICO_LOG_FORMAT="secs,level,module,file" cargo run --release
+//! ```
+//!
+//! `blog-decode` on the host takes the same description.
+
+use core::fmt;
+
+/// Used when `PICO_LOG_FORMAT` isn't set or doesn't parse.
+pub const DEFAULT_SPEC: &str = "hex,core,level,module,color";
+
+/// The format compiled in with `PICO_LOG_FORMAT`.
+pub const BUILD_SPEC: &str = match option_env!("PICO_LOG_FORMAT") {
+    Some(spec) => spec,
+    None => DEFAULT_SPEC,
+};
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    UnknownField,
+    TwoTimestamps,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    None,
+    Hex,
+    Seconds,
+    Delta,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineFormat {
+    pub timestamp: Timestamp,
+    pub core: bool,
+    pub level: bool,
+    pub module: bool,
+    pub source_path: bool,
+    pub color: bool,
+}
+
+impl LineFormat {
+    /// Just the message.
+    pub const fn bare() -> Self {
+        LineFormat {
+            timestamp: Timestamp::None,
+            core: false,
+            level: false,
+            module: false,
+            source_path: false,
+            color: false,
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, FormatError> {
+        let mut format = LineFormat::bare();
+        for field in spec.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let timestamp = match field {
+                "hex" => Timestamp::Hex,
+                "secs" => Timestamp::Seconds,
+                "delta" => Timestamp::Delta,
+                "core" => {
+                    format.core = true;
+                    continue;
+                }
+                "level" => {
+                    format.level = true;
+                    continue;
+                }
+                "module" => {
+                    format.module = true;
+                    continue;
+                }
+                "file" => {
+                    format.source_path = true;
+                    continue;
+                }
+                "color" => {
+                    format.color = true;
+                    continue;
+                }
+                _ => return Err(FormatError::UnknownField),
+            };
+            if format.timestamp != Timestamp::None {
+                return Err(FormatError::TwoTimestamps);
+            }
+            format.timestamp = timestamp;
+        }
+        Ok(format)
+    }
+
+    pub fn log(&self) {
+        log::info!("log format: {}", self);
+    }
+}
+
+/// The description `parse` takes.
+impl fmt::Display for LineFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let timestamp = match self.timestamp {
+            Timestamp::None => None,
+            Timestamp::Hex => Some("hex"),
+            Timestamp::Seconds => Some("secs"),
+            Timestamp::Delta => Some("delta"),
+        };
+        let fields = [
+            (timestamp.is_some(), timestamp.unwrap_or_default()),
+            (self.core, "core"),
+            (self.level, "level"),
+            (self.module, "module"),
+            (self.source_path, "file"),
+            (self.color, "color"),
+        ];
+        let mut separator = "";
+        for (_, name) in fields.iter().filter(|(on, _)| *on) {
+            write!(f, "{}{}", separator, name)?;
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+/// Everything in front of the message, with the separating space.
+pub struct Prefix<'a> {
+    pub format: &'a LineFormat,
+    pub time_us: u64,
+    /// Time since the previous record.
+    pub delta_us: u64,
+    pub core: u8,
+    pub level: log::Level,
+    pub module: &'a str,
+    pub file: &'a str,
+    pub line: u32,
+}
+
+impl fmt::Display for Prefix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = self.format;
+        match format.timestamp {
+            Timestamp::None => {}
+            Timestamp::Hex => write!(f, "{:08x}:", self.time_us)?,
+            Timestamp::Seconds => write!(
+                f,
+                "{}.{:06}:",
+                self.time_us / 1_000_000,
+                self.time_us % 1_000_000
+            )?,
+            Timestamp::Delta => write!(
+                f,
+                "+{}.{:06}:",
+                self.delta_us / 1_000_000,
+                self.delta_us % 1_000_000
+            )?,
+        }
+        if format.core {
+            write!(f, "{}:", self.core)?;
+        }
+        if format.level {
+            write!(f, "[{:7}]", self.level)?;
+        }
+        if format.module || format.source_path {
+            f.write_str("[")?;
+            if format.module {
+                f.write_str(self.module)?;
+            }
+            if format.module && format.source_path {
+                f.write_str(" ")?;
+            }
+            if format.source_path {
+                write!(f, "{}@{}", self.file, self.line)?;
+            }
+            f.write_str("]")?;
+        }
+        let any = format.timestamp != Timestamp::None
+            || format.core
+            || format.level
+            || format.module
+            || format.source_path;
+        if any {
+            f.write_str(" ")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Where finished log records go, see `uart_log`.
+//!
+//! Depends only on `core` and `embedded-io`, the host crate compiles this
+//! file as `log_sink` to run the unit tests below. The firmware doesn't log
+//! through `FmtSink` or `IoSink` itself, only the tests use them.
+
+use core::fmt::Write;
+
+/// Somewhere to send finished records to.
+pub trait LogSink {
+    /// Write a whole record, a formatted line or a `blog` frame.
+    fn write(&mut self, record: &[u8]);
+
+    /// Wait until everything written has left the device.
+    fn flush(&mut self) {}
+}
+
+/// Log to a `core::fmt::Write`. Invalid UTF-8, e.g. in binary log frames,
+/// is replaced.
+#[cfg_attr(target_os = "none", allow(dead_code))]
+pub struct FmtSink<W: Write>(pub W);
+
+impl<W: Write> LogSink for FmtSink<W> {
+    fn write(&mut self, record: &[u8]) {
+        for chunk in record.utf8_chunks() {
+            self.0.write_str(chunk.valid()).ok();
+            if !chunk.invalid().is_empty() {
+                self.0.write_char(char::REPLACEMENT_CHARACTER).ok();
+            }
+        }
+    }
+}
+
+/// Log to an `embedded_io::Write`.
+#[cfg_attr(target_os = "none", allow(dead_code))]
+pub struct IoSink<W: embedded_io::Write>(pub W);
+
+impl<W: embedded_io::Write> LogSink for IoSink<W> {
+    fn write(&mut self, record: &[u8]) {
+        self.0.write_all(record).ok();
+    }
+
+    fn flush(&mut self) {
+        self.0.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fmt_sink() {
+        let mut sink = FmtSink(String::new());
+        sink.write(b"caf\xc3\xa9\r\n");
+        // Each invalid byte, and the start of a cut off character.
+        sink.write(b"ok \xff\xfe end \xc3");
+        sink.flush();
+        assert_eq!(sink.0, "caf\u{e9}\r\nok \u{fffd}\u{fffd} end \u{fffd}");
+    }
+
+    #[test]
+    fn io_sink() {
+        let mut buf = [0u8; 8];
+        let mut sink = IoSink(&mut buf[..]);
+        sink.write(b"abc");
+        sink.write(b"def");
+        sink.flush();
+        // Whatever doesn't fit is dropped.
+        sink.write(b"ghi");
+        assert!(sink.0.is_empty());
+        assert_eq!(&buf, b"abcdefgh");
+    }
+}
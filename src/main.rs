@@ -8,25 +8,79 @@
 //! ```sh
 //! picocom -b 115200 -f n -d 8 -s 1 /dev/tty.usbmodem84102  # macOS
 //! ```
+//!
+//! To log through other pins or UART1, change the `log_uart!` line.
 
 use fugit::RateExtU32;
 use rp2040_hal::dma::DMAExt;
+use rp2040_hal::gpio::bank0;
 use rp2040_hal::gpio::FunctionUart;
+use rp2040_hal::gpio::Pin;
+use rp2040_hal::gpio::PullDown;
 use rp2040_hal::rom_data;
 use rp2040_hal::uart::DataBits;
 use rp2040_hal::uart::StopBits;
 use rp2040_hal::uart::UartConfig;
 use rp2040_hal::uart::UartPeripheral;
 use rp2040_hal::Clock;
-use uart_log::Uart;
 
+mod arena;
+mod blog;
+mod crash;
+mod dma_chain;
+mod dma_log;
+mod dma_random;
 mod experiments;
+mod golden;
+mod guard;
+mod hexdump;
 mod lax_dma;
+mod lax_pio;
+mod log_filter;
+mod log_format;
+mod log_sink;
+mod pattern;
+mod pio_programs;
+mod pio_timing;
+mod profile;
+mod ram_log;
+mod registry;
+mod report;
+mod rng;
+mod scaler;
+mod shell;
 mod time;
 mod uart_log;
 
 const XOSC_CRYSTAL_FREQ: u32 = 12_000_000;
 
+/// Names the UART and the TX and RX pins the log goes through, as the
+/// `LogUart` and `LogPins` types and as `take_log_uart!`, which takes them
+/// out of `pac` and `pins`.
+macro_rules! log_uart {
+    ($uart:ident, $tx:ident: $tx_pin:ident, $rx:ident: $rx_pin:ident) => {
+        type LogUart = rp2040_pac::$uart;
+        type LogPins = (
+            Pin<bank0::$tx_pin, FunctionUart, PullDown>,
+            Pin<bank0::$rx_pin, FunctionUart, PullDown>,
+        );
+
+        macro_rules! take_log_uart {
+            ($pac:ident, $pins:ident) => {
+                (
+                    $pac.$uart,
+                    (
+                        $pins.$tx.into_function::<FunctionUart>(),
+                        $pins.$rx.into_function::<FunctionUart>(),
+                    ),
+                )
+            };
+        }
+    };
+}
+
+log_uart!(UART0, gpio0: Gpio0, gpio1: Gpio1);
+
 /// The linker will place this boot block at the start of our program image. We
 /// need this to help the ROM bootloader get our code up and running.
 /// Note: This boot block is not necessary when using a rp-hal based BSP
@@ -47,18 +101,16 @@ pub static PICOTOOL_ENTRIES: [rp2040_hal::binary_info::EntryAddr; 4] = [
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    log::error!("panic");
-    loop {}
+    crash::on_panic(info)
 }
 
-fn get_pio0_bad() -> rp2040_pac::PIO0 {
-    unsafe { rp2040_pac::PIO0::steal() }
-}
+/// Runs from the ALARM0 interrupt, only to wake up the main loop.
+fn wake() {}
 
 #[rp2040_hal::entry]
 fn main() -> ! {
     let mut pac = rp2040_pac::Peripherals::take().unwrap();
-    let _core = rp2040_pac::CorePeripherals::take().unwrap();
+    let core = rp2040_pac::CorePeripherals::take().unwrap();
 
     // Give more priority to the DMA peripheral
     pac.BUSCTRL.bus_priority().write(|w| {
@@ -92,19 +144,24 @@ fn main() -> ! {
 
     // Initialize the UART logger
     {
-        let uart_pins = (
-            pins.gpio0.into_function::<FunctionUart>(),
-            pins.gpio1.into_function::<FunctionUart>(),
-        );
-
-        let log_uart: Uart = UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
+        let (uart, uart_pins) = take_log_uart!(pac, pins);
+        let log_uart = UartPeripheral::<_, LogUart, LogPins>::new(uart, uart_pins, &mut pac.RESETS)
             .enable(
                 UartConfig::new(115200.Hz(), DataBits::Eight, None, StopBits::One),
                 clocks.peripheral_clock.freq(),
             )
             .unwrap();
 
-        uart_log::init_uart_log(log_uart, uart_log::LogSourcePath::Disabled, true);
+        // Non-blocking, pass `log_uart` itself to wait for every record
+        // to go out instead.
+        let ring =
+            cortex_m::singleton!(: [u8; dma_log::RING_SIZE] = [0; dma_log::RING_SIZE]).unwrap();
+        let dma_uart = dma_log::DmaUart::new(log_uart, ring);
+        let sink = cortex_m::singleton!(: dma_log::DmaUart<LogUart, LogPins> = dma_uart).unwrap();
+
+        uart_log::init_log(sink, log_format::BUILD_SPEC, log_filter::BUILD_SPEC);
+        // Keep the records for post-mortems too.
+        uart_log::add_log_sink(ram_log::init());
     }
 
     log::info!(
@@ -114,29 +171,36 @@ fn main() -> ! {
         rom_data::rom_version_number(),
         time::time_us64()
     );
+    uart_log::log_filter();
+    uart_log::log_format();
+    if crash::report_previous() {
+        ram_log::dump_previous();
+    }
 
-    experiments::run_dma_tests();
-    experiments::test_with_pio_invert_twice(pac.PIO0, &mut pac.RESETS);
-    experiments::test_with_pio_expand_12times(get_pio0_bad(), &mut pac.RESETS);
-    experiments::test_with_pio_expand_dynamic(
-        get_pio0_bad(),
-        &mut pac.RESETS,
-        experiments::MonochromeColor::Bpp1,
-    );
-    experiments::test_with_pio_expand_dynamic(
-        get_pio0_bad(),
-        &mut pac.RESETS,
-        experiments::MonochromeColor::Bpp2,
-    );
-    experiments::test_with_pio_expand_dynamic(
-        get_pio0_bad(),
-        &mut pac.RESETS,
-        experiments::MonochromeColor::Bpp4,
-    );
-
+    let sys_hz = clocks.system_clock.freq().to_Hz();
+    profile::init(core.SYST, sys_hz);
+
+    report::init(report::BUILD_FORMAT);
+    let mut context = registry::Context {
+        resets: &mut pac.RESETS,
+        watchdog: &mut watchdog,
+        sys_hz,
+        params: registry::Params::DEFAULT,
+    };
+    registry::list(&context.params);
+    let skip = registry::recover(registry::BUILD_SPEC, context.watchdog);
+    registry::run_from(registry::BUILD_SPEC, &mut context, skip);
+    report::summary();
+    profile::report();
+
+    let ring =
+        cortex_m::singleton!(: dma_log::RxRing = dma_log::RxRing([0; dma_log::RX_RING_SIZE]))
+            .unwrap();
+    let mut shell = shell::Shell::new(dma_log::DmaUartReader::new::<LogUart>(ring));
+    // Typing raises no interrupt, look every now and then.
+    time::schedule_every(time::Alarm::Alarm0, time::Duration::millis(20), wake);
     loop {
-        //cortex_m::asm::wfe();
-        log::info!("WFE time: {:x}", time::time_us());
-        cortex_m::asm::delay(100_000_000);
+        shell.poll(&mut context);
+        cortex_m::asm::wfi();
     }
 }
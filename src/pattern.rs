@@ -0,0 +1,76 @@
+//! Test data to fill source buffers with.
+
+use crate::rng::Rng;
+use core::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// 0, 1, 2, ... wrapping at 256, shows misplaced bytes best.
+    Counter,
+    Zeros,
+    Ones,
+    /// 0x55, 0xaa, ... for stuck or crossed bits.
+    Alternating,
+    Fill(u8),
+    /// Pseudo-random bytes, the same for the same seed.
+    Random(u32),
+}
+
+impl Pattern {
+    /// `counter`, `zeros`, `ones`, `alt`, a fill byte like `0x5a`, or
+    /// `random` with an optional seed like `random:42`.
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(seed) = s.strip_prefix("random") {
+            return match seed.strip_prefix(':') {
+                Some(seed) => crate::registry::parse_number(seed).map(Pattern::Random),
+                None if seed.is_empty() => Some(Pattern::Random(0)),
+                None => None,
+            };
+        }
+        match s {
+            "counter" => Some(Pattern::Counter),
+            "zeros" => Some(Pattern::Zeros),
+            "ones" => Some(Pattern::Ones),
+            "alt" => Some(Pattern::Alternating),
+            _ => {
+                let hex = s.strip_prefix("0x")?;
+                u8::from_str_radix(hex, 16).ok().map(Pattern::Fill)
+            }
+        }
+    }
+
+    pub fn byte(&self, index: usize) -> u8 {
+        match self {
+            Pattern::Counter => index as u8,
+            Pattern::Zeros => 0,
+            Pattern::Ones => 0xff,
+            Pattern::Alternating => [0x55, 0xaa][index % 2],
+            Pattern::Fill(b) => *b,
+            Pattern::Random(seed) => {
+                // A word per seed, so any byte can be had on its own.
+                let word = Rng::new(seed.wrapping_add((index / 4) as u32)).next_u32();
+                word.to_le_bytes()[index % 4]
+            }
+        }
+    }
+
+    pub fn fill(&self, buffer: &mut [u8]) {
+        for (i, b) in buffer.iter_mut().enumerate() {
+            *b = self.byte(i);
+        }
+    }
+}
+
+/// The description `parse` takes.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Counter => f.write_str("counter"),
+            Pattern::Zeros => f.write_str("zeros"),
+            Pattern::Ones => f.write_str("ones"),
+            Pattern::Alternating => f.write_str("alt"),
+            Pattern::Fill(b) => write!(f, "{:#04x}", b),
+            Pattern::Random(seed) => write!(f, "random:{:#x}", seed),
+        }
+    }
+}
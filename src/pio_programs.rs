@@ -0,0 +1,321 @@
+//! Parameterized PIO program generators.
+//!
+//! All generated programs expect the state machine to run with autopull and
+//! autopush enabled and 32-bit thresholds, so they are fed and drained by
+//! 32-bit DMA transfers to and from the FIFOs. The programs loop via `.wrap`
+//! and never stall on their own.
+//!
+//! Programs can be requested by a short text description, see
+//! [`ProgramSpec::parse`] and [`REGISTRY`].
+//!
+//! Depends only on the `pio` crate, the host crate compiles this file as
+//! `pio_programs` to run the unit tests below.
+
+use pio::InSource;
+use pio::JmpCondition;
+use pio::MovDestination;
+use pio::MovOperation;
+use pio::MovSource;
+use pio::OutDestination;
+use pio::SetDestination;
+
+pub type Program = pio::Program<{ pio::RP2040_MAX_PROGRAM_SIZE }>;
+
+type Assembler = pio::Assembler<{ pio::RP2040_MAX_PROGRAM_SIZE }>;
+
+/// Largest immediate of the `set` instruction (5 bits).
+const SET_MAX: u8 = 31;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProgramError {
+    /// Unknown program name in a description.
+    UnknownProgram,
+    /// Missing or malformed parameter in a description.
+    BadParameter,
+    /// Bits per pixel must divide 32.
+    InvalidBpp(u8),
+    /// The repeat count doesn't fit the `set` immediate.
+    InvalidRepeat(u8),
+    /// Bit width must be 1..=31.
+    InvalidWidth(u8),
+    /// Byte lane must be 0..=3.
+    InvalidLane(u8),
+}
+
+/// Description of a program to generate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProgramSpec {
+    /// Repeat each `bpp`-bit pixel `times` times.
+    BitReplicate { bpp: u8, times: u8 },
+    /// Bitwise invert every word.
+    Invert,
+    /// Keep the low `bits` bits of every word and pack them densely.
+    ShiftPack { bits: u8 },
+    /// Reverse the bit order of every word.
+    BitReverse,
+    /// Swap the nibbles of every byte.
+    NibbleSwap,
+    /// Keep byte `lane` of every word and pack the bytes densely.
+    ByteLaneExtract { lane: u8 },
+}
+
+/// Program names accepted by [`ProgramSpec::parse`] with an example
+/// description and the parameter syntax.
+pub const REGISTRY: &[(&str, &str, &str)] = &[
+    (
+        "replicate",
+        "replicate:2,6",
+        "replicate:<bpp>,<times>  repeat each pixel",
+    ),
+    ("invert", "invert", "invert  invert all bits"),
+    (
+        "pack",
+        "pack:8",
+        "pack:<bits>  pack the low bits of each word",
+    ),
+    (
+        "reverse",
+        "reverse",
+        "reverse  reverse the bits of each word",
+    ),
+    (
+        "nibble_swap",
+        "nibble_swap",
+        "nibble_swap  swap the nibbles of each byte",
+    ),
+    ("lane", "lane:1", "lane:<0..3>  pack one byte of each word"),
+];
+
+impl ProgramSpec {
+    /// Parse a description like `replicate:2,6`, `invert` or `lane:1`.
+    pub fn parse(desc: &str) -> Result<Self, ProgramError> {
+        let (name, args) = desc.trim().split_once(':').unwrap_or((desc.trim(), ""));
+        let mut args = args.split(',').map(|a| a.trim().parse::<u8>());
+        let mut arg = || {
+            args.next()
+                .and_then(|a| a.ok())
+                .ok_or(ProgramError::BadParameter)
+        };
+
+        let spec = match name {
+            "replicate" => ProgramSpec::BitReplicate {
+                bpp: arg()?,
+                times: arg()?,
+            },
+            "invert" => ProgramSpec::Invert,
+            "pack" => ProgramSpec::ShiftPack { bits: arg()? },
+            "reverse" => ProgramSpec::BitReverse,
+            "nibble_swap" => ProgramSpec::NibbleSwap,
+            "lane" => ProgramSpec::ByteLaneExtract { lane: arg()? },
+            _ => return Err(ProgramError::UnknownProgram),
+        };
+        spec.validate()?;
+
+        Ok(spec)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProgramSpec::BitReplicate { .. } => "replicate",
+            ProgramSpec::Invert => "invert",
+            ProgramSpec::ShiftPack { .. } => "pack",
+            ProgramSpec::BitReverse => "reverse",
+            ProgramSpec::NibbleSwap => "nibble_swap",
+            ProgramSpec::ByteLaneExtract { .. } => "lane",
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ProgramError> {
+        match *self {
+            ProgramSpec::BitReplicate { bpp, times } => {
+                if bpp == 0 || bpp > 32 || 32 % bpp != 0 {
+                    return Err(ProgramError::InvalidBpp(bpp));
+                }
+                if times == 0 || times - 1 > SET_MAX {
+                    return Err(ProgramError::InvalidRepeat(times));
+                }
+            }
+            ProgramSpec::ShiftPack { bits } => {
+                if bits == 0 || bits > 31 {
+                    return Err(ProgramError::InvalidWidth(bits));
+                }
+            }
+            ProgramSpec::ByteLaneExtract { lane } => {
+                if lane > 3 {
+                    return Err(ProgramError::InvalidLane(lane));
+                }
+            }
+            ProgramSpec::Invert | ProgramSpec::BitReverse | ProgramSpec::NibbleSwap => {}
+        }
+
+        Ok(())
+    }
+
+    /// Output size relative to the input size as `(numerator, denominator)`.
+    pub fn output_ratio(&self) -> (u32, u32) {
+        match *self {
+            ProgramSpec::BitReplicate { times, .. } => (times as u32, 1),
+            ProgramSpec::ShiftPack { bits } => (bits as u32, 32),
+            ProgramSpec::ByteLaneExtract { .. } => (1, 4),
+            ProgramSpec::Invert | ProgramSpec::BitReverse | ProgramSpec::NibbleSwap => (1, 1),
+        }
+    }
+
+    pub fn generate(&self) -> Result<Program, ProgramError> {
+        self.validate()?;
+
+        let mut a = Assembler::new();
+        match *self {
+            ProgramSpec::BitReplicate { bpp, times } => bit_replicate(&mut a, bpp, times),
+            ProgramSpec::Invert => mov_word(&mut a, MovOperation::Invert),
+            ProgramSpec::ShiftPack { bits } => shift_pack(&mut a, bits),
+            ProgramSpec::BitReverse => mov_word(&mut a, MovOperation::BitReverse),
+            ProgramSpec::NibbleSwap => nibble_swap(&mut a),
+            ProgramSpec::ByteLaneExtract { lane } => byte_lane_extract(&mut a, lane),
+        }
+
+        Ok(a.assemble_program())
+    }
+}
+
+fn bit_replicate(a: &mut Assembler, bpp: u8, times: u8) {
+    let mut repeat = a.label();
+
+    // Pull `bpp` bits from the TX FIFO into OSR
+    a.out(OutDestination::X, bpp);
+    // Loop counter in `Y` to repeat the pixel `times` times
+    a.set(SetDestination::Y, times - 1);
+    a.bind(&mut repeat);
+    // Push the bits into ISR which goes into RX FIFO.
+    a.r#in(InSource::X, bpp);
+    a.jmp(JmpCondition::YDecNonZero, &mut repeat);
+}
+
+fn mov_word(a: &mut Assembler, op: MovOperation) {
+    a.out(OutDestination::X, 32);
+    a.mov(MovDestination::X, op, MovSource::X);
+    a.r#in(InSource::X, 32);
+}
+
+fn shift_pack(a: &mut Assembler, bits: u8) {
+    a.out(OutDestination::X, bits);
+    a.r#in(InSource::X, bits);
+    a.out(OutDestination::NULL, 32 - bits);
+}
+
+fn nibble_swap(a: &mut Assembler) {
+    // Both registers shift to the right: the low nibble comes out first,
+    // and the nibble shifted in first ends up lower.
+    a.out(OutDestination::X, 4);
+    a.out(OutDestination::Y, 4);
+    a.r#in(InSource::Y, 4);
+    a.r#in(InSource::X, 4);
+}
+
+fn byte_lane_extract(a: &mut Assembler, lane: u8) {
+    if lane > 0 {
+        a.out(OutDestination::NULL, 8 * lane);
+    }
+    a.out(OutDestination::X, 8);
+    a.r#in(InSource::X, 8);
+    if lane < 3 {
+        a.out(OutDestination::NULL, 8 * (3 - lane));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            ProgramSpec::parse(" replicate: 2, 6 "),
+            Ok(ProgramSpec::BitReplicate { bpp: 2, times: 6 })
+        );
+        assert_eq!(ProgramSpec::parse("invert"), Ok(ProgramSpec::Invert));
+        assert_eq!(
+            ProgramSpec::parse("lane:3"),
+            Ok(ProgramSpec::ByteLaneExtract { lane: 3 })
+        );
+        assert_eq!(
+            ProgramSpec::parse("swap"),
+            Err(ProgramError::UnknownProgram)
+        );
+        assert_eq!(
+            ProgramSpec::parse("replicate:2"),
+            Err(ProgramError::BadParameter)
+        );
+        assert_eq!(
+            ProgramSpec::parse("pack:x"),
+            Err(ProgramError::BadParameter)
+        );
+        assert_eq!(
+            ProgramSpec::parse("pack:256"),
+            Err(ProgramError::BadParameter)
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let replicate = |bpp, times| ProgramSpec::BitReplicate { bpp, times }.validate();
+        for bpp in [1, 2, 4, 8, 16, 32] {
+            assert_eq!(replicate(bpp, 1), Ok(()));
+        }
+        for bpp in [0, 3, 5, 12, 24, 64] {
+            assert_eq!(replicate(bpp, 1), Err(ProgramError::InvalidBpp(bpp)));
+        }
+        assert_eq!(replicate(1, SET_MAX + 1), Ok(()));
+        assert_eq!(
+            replicate(1, SET_MAX + 2),
+            Err(ProgramError::InvalidRepeat(SET_MAX + 2))
+        );
+        assert_eq!(replicate(1, 0), Err(ProgramError::InvalidRepeat(0)));
+
+        let pack = |bits| ProgramSpec::ShiftPack { bits }.validate();
+        assert_eq!(pack(1), Ok(()));
+        assert_eq!(pack(31), Ok(()));
+        assert_eq!(pack(0), Err(ProgramError::InvalidWidth(0)));
+        assert_eq!(pack(32), Err(ProgramError::InvalidWidth(32)));
+
+        let lane = |lane| ProgramSpec::ByteLaneExtract { lane }.validate();
+        assert_eq!(lane(0), Ok(()));
+        assert_eq!(lane(3), Ok(()));
+        assert_eq!(lane(4), Err(ProgramError::InvalidLane(4)));
+
+        // `generate` checks too.
+        assert_eq!(
+            ProgramSpec::ShiftPack { bits: 32 }.generate().unwrap_err(),
+            ProgramError::InvalidWidth(32)
+        );
+    }
+
+    #[test]
+    fn registry_examples_fit() {
+        for &(name, example, _) in REGISTRY {
+            let spec = ProgramSpec::parse(example).unwrap();
+            assert_eq!(spec.name(), name);
+            let program = spec.generate().unwrap();
+            assert!(
+                program.code.len() <= pio::RP2040_MAX_PROGRAM_SIZE,
+                "{example}"
+            );
+        }
+
+        // The largest parameters too.
+        for spec in [
+            ProgramSpec::BitReplicate {
+                bpp: 32,
+                times: SET_MAX + 1,
+            },
+            ProgramSpec::ShiftPack { bits: 31 },
+            ProgramSpec::ByteLaneExtract { lane: 3 },
+            ProgramSpec::NibbleSwap,
+        ] {
+            let program = spec.generate().unwrap();
+            assert!(program.code.len() <= pio::RP2040_MAX_PROGRAM_SIZE);
+            assert_eq!(program.wrap.target, 0);
+            assert_eq!(program.wrap.source as usize, program.code.len() - 1);
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! Static timing analysis of PIO programs.
+//!
+//! Depends only on the `pio` and `log` crates, so it builds for the host as
+//! well as for the target: the host crate compiles this very file as
+//! `pio_timing`, where the unit tests below run.
+//!
+//! The analysis finds the loops in the program and then runs the program on
+//! a small model of a state machine assuming the TX FIFO never runs dry and the
+//! RX FIFO never fills up (the best DMA can do), so `irq wait`, `wait` and
+//! blocking `pull`/`push` never stall. Data-dependent branches are resolved by
+//! running the model over several input patterns and keeping the worst case.
+//! Bits are shifted out of OSR and into ISR to the right, like the
+//! `PIOBuilder` default.
+
+use pio::ArrayVec;
+use pio::InSource;
+use pio::Instruction;
+use pio::InstructionOperands;
+use pio::JmpCondition;
+use pio::MovDestination;
+use pio::MovOperation;
+use pio::MovSource;
+use pio::OutDestination;
+use pio::SetDestination;
+
+/// Input patterns the model is run on to resolve data-dependent branches.
+const PATTERNS: [u32; 4] = [0x0000_0000, 0xffff_ffff, 0xaaaa_aaaa, 0x5555_5555];
+
+/// Number of input words to run the model for, per pattern.
+const WORDS: u32 = 8;
+
+/// Give up if the program doesn't consume `WORDS` in this many cycles.
+const MAX_CYCLES: u32 = 1 << 16;
+
+/// Every instruction may jump back, plus the wrap.
+const MAX_LOOPS: usize = pio::RP2040_MAX_PROGRAM_SIZE + 1;
+
+/// The part of the state machine configuration that affects timing.
+#[derive(Copy, Clone)]
+pub struct SmConfig {
+    pub autopull: bool,
+    /// 1..=32 bits, 0 means 32 like in the hardware.
+    pub pull_threshold: u8,
+    pub autopush: bool,
+    /// 1..=32 bits, 0 means 32 like in the hardware.
+    pub push_threshold: u8,
+    /// Clock divider as integer and 1/256 fractional part.
+    pub clkdiv: (u16, u8),
+}
+
+impl Default for SmConfig {
+    fn default() -> Self {
+        SmConfig {
+            autopull: false,
+            pull_threshold: 32,
+            autopush: false,
+            push_threshold: 32,
+            clkdiv: (1, 0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct LoopTiming {
+    /// First instruction of the loop body.
+    pub start: u8,
+    /// The instruction jumping back to `start` (or the wrap source).
+    pub end: u8,
+    /// Cycles of one straight pass through the body, including delays.
+    pub body_cycles: u32,
+    /// The longest pass seen in the model, including nested loops.
+    pub max_pass_cycles: u32,
+    /// The largest number of back-to-back passes seen in the model, `None`
+    /// for loops that never exit (`.wrap` or `jmp` without a condition).
+    pub max_iterations: Option<u32>,
+}
+
+impl LoopTiming {
+    fn new<const N: usize>(
+        program: &pio::Program<N>,
+        start: u8,
+        end: u8,
+        exits: bool,
+    ) -> Result<Self, TimingError> {
+        let mut body_cycles = 0;
+        for pc in start..=end {
+            body_cycles += cycles(&decode(program, pc)?);
+        }
+        Ok(LoopTiming {
+            start,
+            end,
+            body_cycles,
+            max_pass_cycles: body_cycles,
+            max_iterations: if exits { Some(0) } else { None },
+        })
+    }
+
+    /// Worst-case cycles from entering the loop to leaving it.
+    pub fn max_cycles(&self) -> Option<u32> {
+        self.max_iterations.map(|n| n * self.max_pass_cycles)
+    }
+
+    fn contains(&self, pc: u8) -> bool {
+        self.start <= pc && pc <= self.end
+    }
+}
+
+/// Why a program can't be analyzed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimingError {
+    /// The word at this address isn't an instruction, e.g. a reserved
+    /// destination or one that doesn't fit the side-set.
+    InvalidInstruction(u8),
+}
+
+#[derive(Debug)]
+pub struct ProgramTiming {
+    pub loops: ArrayVec<LoopTiming, { MAX_LOOPS }>,
+    /// Worst-case cycles between two consecutive pulls from the TX FIFO.
+    pub cycles_per_input_word: Option<u32>,
+    /// Worst-case cycles between two consecutive pushes to the RX FIFO.
+    pub cycles_per_output_word: Option<u32>,
+}
+
+/// State machine clock in Hz for the given system clock and divider.
+pub fn sm_clock_hz(sys_hz: u32, clkdiv: (u16, u8)) -> u32 {
+    let div = ((clkdiv.0 as u64) << 8) | clkdiv.1 as u64;
+    let div = if div == 0 { 1 << 16 } else { div };
+    (((sys_hz as u64) << 8) / div) as u32
+}
+
+impl ProgramTiming {
+    /// Maximum rate the program can consume input at, in bytes per second.
+    pub fn max_input_bytes_per_sec(&self, sys_hz: u32, clkdiv: (u16, u8)) -> Option<u32> {
+        self.cycles_per_input_word
+            .map(|c| bytes_per_sec(sm_clock_hz(sys_hz, clkdiv), c))
+    }
+
+    /// Maximum rate the program can produce output at, in bytes per second.
+    pub fn max_output_bytes_per_sec(&self, sys_hz: u32, clkdiv: (u16, u8)) -> Option<u32> {
+        self.cycles_per_output_word
+            .map(|c| bytes_per_sec(sm_clock_hz(sys_hz, clkdiv), c))
+    }
+
+    pub fn log(&self, name: &str, sys_hz: u32, clkdiv: (u16, u8)) {
+        log::info!(
+            "PIO timing of {}: sm clock {} Hz (sys {} Hz, clkdiv {}+{}/256)",
+            name,
+            sm_clock_hz(sys_hz, clkdiv),
+            sys_hz,
+            clkdiv.0,
+            clkdiv.1
+        );
+        for l in self.loops.iter() {
+            log::info!(
+                "  loop {:2}..={:2}: {} cycles/pass ({} straight), up to {:?} passes, {:?} cycles",
+                l.start,
+                l.end,
+                l.max_pass_cycles,
+                l.body_cycles,
+                l.max_iterations,
+                l.max_cycles()
+            );
+        }
+        log::info!(
+            "  input: {:?} cycles/word, max {:?} B/s; output: {:?} cycles/word, max {:?} B/s",
+            self.cycles_per_input_word,
+            self.max_input_bytes_per_sec(sys_hz, clkdiv),
+            self.cycles_per_output_word,
+            self.max_output_bytes_per_sec(sys_hz, clkdiv),
+        );
+    }
+}
+
+fn bytes_per_sec(sm_hz: u32, cycles_per_word: u32) -> u32 {
+    ((sm_hz as u64 * 4) / cycles_per_word.max(1) as u64) as u32
+}
+
+fn decode<const N: usize>(program: &pio::Program<N>, pc: u8) -> Result<Instruction, TimingError> {
+    program
+        .code
+        .get(pc as usize)
+        .and_then(|&code| Instruction::decode(code, program.side_set))
+        .ok_or(TimingError::InvalidInstruction(pc))
+}
+
+fn cycles(instr: &Instruction) -> u32 {
+    1 + instr.delay as u32
+}
+
+fn threshold(bits: u8) -> u32 {
+    match bits {
+        0 => 32,
+        n => n.min(32) as u32,
+    }
+}
+
+/// Analyze the timing of `program` running with `config`.
+pub fn analyze<const N: usize>(
+    program: &pio::Program<N>,
+    config: &SmConfig,
+) -> Result<ProgramTiming, TimingError> {
+    let mut loops = ArrayVec::new();
+    for pc in 0..program.code.len() as u8 {
+        if let InstructionOperands::JMP { condition, address } = decode(program, pc)?.operands {
+            if address <= pc {
+                let exits = !matches!(condition, JmpCondition::Always);
+                loops.push(LoopTiming::new(program, address, pc, exits)?);
+            }
+        }
+    }
+    let wrap = program.wrap;
+    if !loops
+        .iter()
+        .any(|l| l.start == wrap.target && l.end == wrap.source)
+    {
+        loops.push(LoopTiming::new(program, wrap.target, wrap.source, false)?);
+    }
+
+    let mut timing = ProgramTiming {
+        loops,
+        cycles_per_input_word: None,
+        cycles_per_output_word: None,
+    };
+    for pattern in PATTERNS {
+        let mut model = Model::new(config, pattern);
+        model.run(program, &mut timing)?;
+        timing.cycles_per_input_word = timing.cycles_per_input_word.max(model.pulls.worst);
+        timing.cycles_per_output_word = timing.cycles_per_output_word.max(model.pushes.worst);
+    }
+
+    Ok(timing)
+}
+
+/// Tracks the worst-case distance between FIFO events.
+#[derive(Default)]
+struct Events {
+    count: u32,
+    last: Option<u32>,
+    worst: Option<u32>,
+}
+
+impl Events {
+    fn record(&mut self, cycle: u32) {
+        if let Some(last) = self.last {
+            self.worst = self.worst.max(Some(cycle - last));
+        }
+        self.last = Some(cycle);
+        self.count += 1;
+    }
+}
+
+struct Model {
+    autopull: bool,
+    pull_threshold: u32,
+    autopush: bool,
+    push_threshold: u32,
+    pattern: u32,
+    x: u32,
+    y: u32,
+    osr: u32,
+    osr_count: u32,
+    isr: u32,
+    isr_count: u32,
+    cycle: u32,
+    pulls: Events,
+    pushes: Events,
+}
+
+impl Model {
+    fn new(config: &SmConfig, pattern: u32) -> Self {
+        Model {
+            autopull: config.autopull,
+            pull_threshold: threshold(config.pull_threshold),
+            autopush: config.autopush,
+            push_threshold: threshold(config.push_threshold),
+            pattern,
+            x: 0,
+            y: 0,
+            osr: 0,
+            osr_count: 32,
+            isr: 0,
+            isr_count: 0,
+            cycle: 0,
+            pulls: Events::default(),
+            pushes: Events::default(),
+        }
+    }
+
+    fn pull(&mut self) {
+        self.osr = self.pattern;
+        self.osr_count = 0;
+        self.pulls.record(self.cycle);
+    }
+
+    fn push(&mut self) {
+        self.isr = 0;
+        self.isr_count = 0;
+        self.pushes.record(self.cycle);
+    }
+
+    fn autopull(&mut self) {
+        if self.autopull && self.osr_count >= self.pull_threshold {
+            self.pull();
+        }
+    }
+
+    fn shift_in(&mut self, value: u32, bits: u32) {
+        let value = if bits == 32 {
+            value
+        } else {
+            value & ((1 << bits) - 1)
+        };
+        self.isr = if bits == 32 {
+            value
+        } else {
+            (self.isr >> bits) | (value << (32 - bits))
+        };
+        self.isr_count = (self.isr_count + bits).min(32);
+        if self.autopush && self.isr_count >= self.push_threshold {
+            self.push();
+        }
+    }
+
+    fn shift_out(&mut self, bits: u32) -> u32 {
+        let value = if bits == 32 {
+            self.osr
+        } else {
+            self.osr & ((1 << bits) - 1)
+        };
+        self.osr = if bits == 32 { 0 } else { self.osr >> bits };
+        self.osr_count = (self.osr_count + bits).min(32);
+        value
+    }
+
+    fn mov_source(&self, source: MovSource) -> u32 {
+        match source {
+            MovSource::X => self.x,
+            MovSource::Y => self.y,
+            MovSource::ISR => self.isr,
+            MovSource::OSR => self.osr,
+            MovSource::PINS | MovSource::NULL | MovSource::STATUS => 0,
+        }
+    }
+
+    fn run<const N: usize>(
+        &mut self,
+        program: &pio::Program<N>,
+        timing: &mut ProgramTiming,
+    ) -> Result<(), TimingError> {
+        let wrap = program.wrap;
+        let mut passes = [(0u32, None::<u32>); MAX_LOOPS];
+        let mut pc = 0u8;
+
+        self.autopull();
+        while self.pulls.count <= WORDS && self.cycle < MAX_CYCLES {
+            let instr = decode(program, pc)?;
+            let mut next = if pc == wrap.source {
+                wrap.target
+            } else {
+                pc + 1
+            };
+
+            match instr.operands {
+                InstructionOperands::JMP { condition, address } => {
+                    let taken = match condition {
+                        JmpCondition::Always => true,
+                        JmpCondition::XIsZero => self.x == 0,
+                        JmpCondition::XDecNonZero => {
+                            let taken = self.x != 0;
+                            self.x = self.x.wrapping_sub(1);
+                            taken
+                        }
+                        JmpCondition::YIsZero => self.y == 0,
+                        JmpCondition::YDecNonZero => {
+                            let taken = self.y != 0;
+                            self.y = self.y.wrapping_sub(1);
+                            taken
+                        }
+                        JmpCondition::XNotEqualY => self.x != self.y,
+                        JmpCondition::PinHigh => false,
+                        JmpCondition::OutputShiftRegisterNotEmpty => {
+                            self.osr_count < self.pull_threshold
+                        }
+                    };
+                    if taken {
+                        next = address;
+                    }
+                }
+                InstructionOperands::WAIT { .. } | InstructionOperands::IRQ { .. } => {}
+                InstructionOperands::IN { source, bit_count } => {
+                    let value = match source {
+                        InSource::X => self.x,
+                        InSource::Y => self.y,
+                        InSource::ISR => self.isr,
+                        InSource::OSR => self.osr,
+                        InSource::PINS | InSource::NULL => 0,
+                    };
+                    self.shift_in(value, threshold(bit_count));
+                }
+                InstructionOperands::OUT {
+                    destination,
+                    bit_count,
+                } => {
+                    self.autopull();
+                    let value = self.shift_out(threshold(bit_count));
+                    match destination {
+                        OutDestination::X => self.x = value,
+                        OutDestination::Y => self.y = value,
+                        OutDestination::ISR => {
+                            self.isr = value;
+                            self.isr_count = threshold(bit_count);
+                        }
+                        OutDestination::PC => next = (value % program.code.len() as u32) as u8,
+                        OutDestination::PINS
+                        | OutDestination::NULL
+                        | OutDestination::PINDIRS
+                        | OutDestination::EXEC => {}
+                    }
+                    self.autopull();
+                }
+                InstructionOperands::PUSH { if_full, .. } => {
+                    if !if_full || self.isr_count >= self.push_threshold {
+                        self.push();
+                    }
+                }
+                InstructionOperands::PULL { if_empty, .. } => {
+                    if !if_empty || self.osr_count >= self.pull_threshold {
+                        self.pull();
+                    }
+                }
+                InstructionOperands::MOV {
+                    destination,
+                    op,
+                    source,
+                } => {
+                    let value = self.mov_source(source);
+                    let value = match op {
+                        MovOperation::None => value,
+                        MovOperation::Invert => !value,
+                        MovOperation::BitReverse => value.reverse_bits(),
+                    };
+                    match destination {
+                        MovDestination::X => self.x = value,
+                        MovDestination::Y => self.y = value,
+                        MovDestination::ISR => {
+                            self.isr = value;
+                            self.isr_count = 0;
+                        }
+                        MovDestination::OSR => {
+                            self.osr = value;
+                            self.osr_count = 0;
+                        }
+                        MovDestination::PC => next = (value % program.code.len() as u32) as u8,
+                        MovDestination::PINS | MovDestination::EXEC => {}
+                    }
+                }
+                InstructionOperands::SET { destination, data } => match destination {
+                    SetDestination::X => self.x = data as u32,
+                    SetDestination::Y => self.y = data as u32,
+                    SetDestination::PINS | SetDestination::PINDIRS => {}
+                },
+            }
+
+            // Count back-to-back passes through each loop and time them:
+            // taking the back edge starts the next pass, leaving the body
+            // ends the run.
+            let start_cycle = self.cycle;
+            self.cycle += cycles(&instr);
+            for (l, (passes, pass_start)) in timing.loops.iter_mut().zip(passes.iter_mut()) {
+                if pc == l.start && pass_start.is_none() {
+                    *pass_start = Some(start_cycle);
+                }
+                // An inner loop may share the last instruction: jumping
+                // elsewhere inside the body doesn't finish the pass.
+                if pc == l.end && (next == l.start || !l.contains(next)) {
+                    *passes += 1;
+                    if let Some(n) = l.max_iterations.as_mut() {
+                        *n = (*n).max(*passes);
+                    }
+                    if let Some(s) = pass_start.take() {
+                        l.max_pass_cycles = l.max_pass_cycles.max(self.cycle - s);
+                    }
+                    if next == l.start {
+                        *pass_start = Some(self.cycle);
+                    } else {
+                        *passes = 0;
+                    }
+                } else if !l.contains(next) {
+                    *passes = 0;
+                    *pass_start = None;
+                }
+            }
+
+            pc = next;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Assembler = pio::Assembler<{ pio::RP2040_MAX_PROGRAM_SIZE }>;
+
+    const STREAM: SmConfig = SmConfig {
+        autopull: true,
+        pull_threshold: 32,
+        autopush: true,
+        push_threshold: 32,
+        clkdiv: (1, 0),
+    };
+
+    /// `expand_times12_pio` from the experiments.
+    fn expand_times12() -> pio::Program<{ pio::RP2040_MAX_PROGRAM_SIZE }> {
+        let mut a = Assembler::new();
+        let mut repeat = a.label();
+        a.out(OutDestination::X, 1);
+        a.set(SetDestination::Y, 11);
+        a.bind(&mut repeat);
+        a.r#in(InSource::X, 1);
+        a.jmp(JmpCondition::YDecNonZero, &mut repeat);
+        a.assemble_program()
+    }
+
+    #[test]
+    fn jmp_y_dec_loop() {
+        let timing = analyze(&expand_times12(), &STREAM).unwrap();
+
+        // The `jmp y--` loop runs 12 times, the wrap forever.
+        let inner = timing.loops[0];
+        assert_eq!((inner.start, inner.end), (2, 3));
+        assert_eq!(inner.body_cycles, 2);
+        assert_eq!(inner.max_pass_cycles, 2);
+        assert_eq!(inner.max_iterations, Some(12));
+        assert_eq!(inner.max_cycles(), Some(24));
+        let wrap = timing.loops[1];
+        assert_eq!((wrap.start, wrap.end), (0, 3));
+        assert_eq!(wrap.max_pass_cycles, 26);
+        assert_eq!(wrap.max_iterations, None);
+
+        // 32 pixels of `out`, `set` and 12 times `in`, `jmp` per input word.
+        assert_eq!(timing.cycles_per_input_word, Some(32 * 26));
+        // 32 `in`s per output word, with up to 3 `out`, `set` pairs between.
+        assert_eq!(timing.cycles_per_output_word, Some(32 * 2 + 3 * 2));
+    }
+
+    #[test]
+    fn delays() {
+        let mut a = Assembler::new();
+        a.out_with_delay(OutDestination::X, 32, 3);
+        a.mov_with_delay(MovDestination::X, MovOperation::Invert, MovSource::X, 1);
+        a.in_with_delay(InSource::X, 32, 6);
+        let program = a.assemble_program();
+
+        let timing = analyze(&program, &STREAM).unwrap();
+        assert_eq!(timing.loops.len(), 1);
+        assert_eq!(timing.loops[0].body_cycles, 4 + 2 + 7);
+        assert_eq!(timing.cycles_per_input_word, Some(13));
+        assert_eq!(timing.cycles_per_output_word, Some(13));
+
+        // 125 MHz / 13 cycles per word, halved by the divider.
+        assert_eq!(sm_clock_hz(125_000_000, (2, 0)), 62_500_000);
+        assert_eq!(
+            timing.max_input_bytes_per_sec(125_000_000, (2, 0)),
+            Some(62_500_000 * 4 / 13)
+        );
+    }
+
+    #[test]
+    fn data_dependent_branch() {
+        // Every set bit costs a delayed `nop`: the worst case is all ones.
+        let mut a = Assembler::new();
+        let mut top = a.label();
+        let mut zero = a.label();
+        a.bind(&mut top);
+        a.out(OutDestination::X, 1);
+        a.jmp(JmpCondition::XIsZero, &mut zero);
+        a.nop_with_delay(7);
+        a.bind(&mut zero);
+        a.jmp(JmpCondition::Always, &mut top);
+        let program = a.assemble_program();
+
+        let timing = analyze(&program, &SmConfig::default()).unwrap();
+        // Without autopull nothing is ever pulled.
+        assert_eq!(timing.cycles_per_input_word, None);
+
+        let timing = analyze(&program, &STREAM).unwrap();
+        assert_eq!(timing.cycles_per_input_word, Some(32 * (1 + 1 + 8 + 1)));
+    }
+
+    #[test]
+    fn invalid_instruction() {
+        let mut program = expand_times12();
+        // `set` to a reserved destination.
+        program.code[1] = 0xe060;
+        assert_eq!(
+            analyze(&program, &STREAM).unwrap_err(),
+            TimingError::InvalidInstruction(1)
+        );
+    }
+}
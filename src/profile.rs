@@ -0,0 +1,190 @@
+//! Cycle counting for things too short for the microsecond timer.
+//!
+//! SysTick counts core clock cycles down from 2^24, its exception counts
+//! the wraps. A `Scope` measures from its creation to its drop and adds
+//! the count to the statistics of its name; `report` logs them all:
+//!
+//! ```ignore
+//! for _ in 0..16 {
+//!     let _scope = profile::Scope::new("dma_simple");
+//!     dma.trigger();
+//!     dma.wait();
+//! }
+//! profile::report();
+//! ```
+//!
+//! The cost of an empty scope is measured at `init` and subtracted. SysTick
+//! belongs to the core that calls `init`, only measure on that core. With
+//! interrupts masked for longer than a wrap (~134 ms at 125 MHz) the count
+//! comes out short by a multiple of 2^24 cycles.
+
+use core::cell::Cell;
+use core::cell::RefCell;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SCB;
+use cortex_m::peripheral::SYST;
+use cortex_m_rt::exception;
+use critical_section::Mutex;
+
+const RELOAD: u32 = 0x00ff_ffff;
+const MAX_SCOPES: usize = 32;
+
+/// Only the SysTick exception writes it.
+static WRAPS: AtomicU32 = AtomicU32::new(0);
+static SYS_HZ: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+static OVERHEAD: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+#[exception]
+fn SysTick() {
+    WRAPS.store(WRAPS.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+}
+
+/// Cycles since `init`.
+pub fn cycles() -> u64 {
+    loop {
+        let wraps = WRAPS.load(Ordering::Relaxed);
+        let current = SYST::get_current();
+        // Wrapped, but the exception hasn't run yet, e.g. with interrupts
+        // masked.
+        let pending = SCB::is_pendst_pending() as u32;
+        let current = if pending != 0 {
+            SYST::get_current()
+        } else {
+            current
+        };
+        if WRAPS.load(Ordering::Relaxed) == wraps {
+            return ((wraps + pending) as u64) << 24 | (RELOAD - current) as u64;
+        }
+    }
+}
+
+/// Start counting cycles at `sys_hz`, the core clock. SysTick belongs to
+/// the exception and `cycles` from now on.
+pub fn init(mut syst: SYST, sys_hz: u32) {
+    syst.disable_counter();
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(RELOAD);
+    syst.clear_current();
+    syst.enable_interrupt();
+    syst.enable_counter();
+
+    let overhead = (0..8)
+        .map(|_| {
+            let start = cycles();
+            cycles() - start
+        })
+        .min()
+        .unwrap_or(0);
+    critical_section::with(|cs| {
+        SYS_HZ.borrow(cs).set(sys_hz);
+        OVERHEAD.borrow(cs).set(overhead);
+    });
+    log::debug!("profile: {} Hz, scope overhead {} cycles", sys_hz, overhead);
+}
+
+#[derive(Copy, Clone)]
+struct Stats {
+    name: &'static str,
+    count: u32,
+    min: u64,
+    max: u64,
+    total: u64,
+}
+
+static STATS: Mutex<RefCell<[Option<Stats>; MAX_SCOPES]>> =
+    Mutex::new(RefCell::new([None; MAX_SCOPES]));
+
+/// Add a measurement to `name`'s statistics. Dropped if there are
+/// `MAX_SCOPES` names already.
+pub fn record(name: &'static str, cycles: u64) {
+    critical_section::with(|cs| {
+        let mut stats = STATS.borrow_ref_mut(cs);
+        let slot = match stats.iter().position(|s| s.is_some_and(|s| s.name == name)) {
+            Some(i) => &mut stats[i],
+            None => match stats.iter_mut().find(|s| s.is_none()) {
+                Some(slot) => slot,
+                None => return,
+            },
+        };
+        let s = slot.get_or_insert(Stats {
+            name,
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+            total: 0,
+        });
+        s.count += 1;
+        s.min = s.min.min(cycles);
+        s.max = s.max.max(cycles);
+        s.total += cycles;
+    });
+}
+
+/// Measures from `new` to drop.
+pub struct Scope {
+    name: &'static str,
+    start: u64,
+}
+
+impl Scope {
+    pub fn new(name: &'static str) -> Self {
+        Scope {
+            name,
+            start: cycles(),
+        }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let elapsed = cycles() - self.start;
+        let overhead = critical_section::with(|cs| OVERHEAD.borrow(cs).get());
+        record(self.name, elapsed.saturating_sub(overhead));
+    }
+}
+
+/// Run `f` in a scope named `name`.
+#[allow(dead_code)]
+pub fn measure<R>(name: &'static str, f: impl FnOnce() -> R) -> R {
+    let _scope = Scope::new(name);
+    f()
+}
+
+/// `cycles` at `sys_hz` as nanoseconds.
+fn ns(cycles: u64, sys_hz: u32) -> u64 {
+    cycles * 1_000_000_000 / (sys_hz.max(1) as u64)
+}
+
+/// Log the statistics of every scope.
+pub fn report() {
+    let (stats, sys_hz) =
+        critical_section::with(|cs| (*STATS.borrow_ref(cs), SYS_HZ.borrow(cs).get()));
+    log::info!(
+        "{:24} {:>6} {:>10} {:>10} {:>10} {:>10}",
+        "scope",
+        "count",
+        "min cyc",
+        "mean cyc",
+        "max cyc",
+        "mean ns"
+    );
+    for s in stats.iter().flatten() {
+        let mean = s.total / s.count as u64;
+        log::info!(
+            "{:24} {:>6} {:>10} {:>10} {:>10} {:>10}",
+            s.name,
+            s.count,
+            s.min,
+            mean,
+            s.max,
+            ns(mean, sys_hz)
+        );
+    }
+}
+
+/// Forget all statistics.
+pub fn reset() {
+    critical_section::with(|cs| *STATS.borrow_ref_mut(cs) = [None; MAX_SCOPES]);
+}
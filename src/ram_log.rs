@@ -0,0 +1,184 @@
+//! Log capture in RAM for when nothing is listening on the UART.
+//!
+//! The last `RAM_LOG_SIZE` bytes of records are kept in a ring in RAM that
+//! isn't zeroed at startup, so after a soft reset (watchdog, debugger,
+//! panic reboot) the previous boot's records are still there. They can be
+//! sent to the other sinks with `dump`, or pulled out of a RAM dump taken
+//! with a debugger:
+//!
+//! ```sh
+//! (gdb) dump binary memory ram.bin 0x20000000 0x20042000
+//! cd host && cargo run --bin ramlog-extract -- ram.bin
+//! ```
+//!
+//! The header starts with `RAM_LOG_MAGIC` and its complement so the
+//! extractor can find it anywhere in the dump.
+
+use crate::log_sink::LogSink;
+use core::mem::MaybeUninit;
+
+pub const RAM_LOG_SIZE: usize = 16 * 1024;
+
+const RAM_LOG_MAGIC: u32 = 0x474f_4c52;
+
+#[repr(C)]
+struct Header {
+    magic: u32,
+    magic_inv: u32,
+    size: u32,
+    /// Total bytes ever written.
+    written: u32,
+    /// `written` when the current boot started.
+    boot_start: u32,
+    boots: u32,
+    /// Xor of the fields above.
+    check: u32,
+}
+
+impl Header {
+    fn compute_check(&self) -> u32 {
+        self.magic ^ self.magic_inv ^ self.size ^ self.written ^ self.boot_start ^ self.boots
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == RAM_LOG_MAGIC
+            && self.magic_inv == !RAM_LOG_MAGIC
+            && self.size == RAM_LOG_SIZE as u32
+            && self.check == self.compute_check()
+    }
+}
+
+#[repr(C)]
+struct RamLog {
+    header: Header,
+    data: [u8; RAM_LOG_SIZE],
+}
+
+impl RamLog {
+    fn append(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.data[self.header.written as usize % RAM_LOG_SIZE] = b;
+            self.header.written = self.header.written.wrapping_add(1);
+        }
+        self.header.check = self.header.compute_check();
+    }
+
+    /// The oldest position still in the ring.
+    fn oldest(&self) -> u32 {
+        self.header.written.saturating_sub(RAM_LOG_SIZE as u32)
+    }
+}
+
+#[link_section = ".uninit.RAM_LOG"]
+static mut RAM_LOG: MaybeUninit<RamLog> = MaybeUninit::uninit();
+
+/// Set while dumping, so the dump doesn't record itself.
+static mut DUMPING: bool = false;
+
+/// The previous boot's records, as positions in the ring.
+static mut PREVIOUS: (u32, u32) = (0, 0);
+
+#[allow(static_mut_refs)]
+fn ram_log() -> &'static mut RamLog {
+    unsafe { RAM_LOG.assume_init_mut() }
+}
+
+/// The sink to pass to `uart_log::add_log_sink`.
+pub struct RamLogSink;
+
+impl LogSink for RamLogSink {
+    fn write(&mut self, record: &[u8]) {
+        // Only a copy, short enough for a critical section, which keeps
+        // `dump` from reading halfway through.
+        critical_section::with(|_| unsafe {
+            if !DUMPING {
+                ram_log().append(record);
+            }
+        });
+    }
+}
+
+/// Keep the records from the previous boot if the ring survived, start
+/// a new one otherwise.
+pub fn init() -> &'static mut RamLogSink {
+    let log = unsafe {
+        // Start over if the header is garbage. Only plain integers live in
+        // there, so any contents are fine to look at.
+        #[allow(static_mut_refs)]
+        let header = RAM_LOG.as_mut_ptr().cast::<Header>();
+        if !(*header).is_valid() {
+            header.write(Header {
+                magic: RAM_LOG_MAGIC,
+                magic_inv: !RAM_LOG_MAGIC,
+                size: RAM_LOG_SIZE as u32,
+                written: 0,
+                boot_start: 0,
+                boots: 0,
+                check: 0,
+            });
+        }
+        ram_log()
+    };
+
+    let header = &mut log.header;
+    unsafe {
+        PREVIOUS = (header.boot_start, header.written);
+    }
+    header.boot_start = header.written;
+    header.boots = header.boots.wrapping_add(1);
+    header.check = header.compute_check();
+
+    cortex_m::singleton!(: RamLogSink = RamLogSink).unwrap()
+}
+
+/// Send the records in `start..end` to the other sinks.
+fn dump_range(start: u32, end: u32) {
+    let mut chunk = [0u8; 256];
+    let mut pos = start;
+    while pos < end {
+        let len = critical_section::with(|_| {
+            let log = ram_log();
+            // Whatever was overwritten since is gone.
+            pos = pos.max(log.oldest());
+            let len = (end.saturating_sub(pos) as usize).min(chunk.len());
+            for (i, b) in chunk[..len].iter_mut().enumerate() {
+                *b = log.data[(pos as usize + i) % RAM_LOG_SIZE];
+            }
+            len
+        });
+        if len == 0 {
+            break;
+        }
+        pos += len as u32;
+
+        unsafe { DUMPING = true };
+        crate::uart_log::write_record(&chunk[..len]);
+        // The DMA transport drops what doesn't fit.
+        log::logger().flush();
+        unsafe { DUMPING = false };
+    }
+}
+
+/// Send everything in the ring to the other sinks.
+pub fn dump() {
+    let (oldest, written) = critical_section::with(|_| {
+        let log = ram_log();
+        (log.oldest(), log.header.written)
+    });
+    log::info!("--- RAM log, {} bytes", written - oldest);
+    dump_range(oldest, written);
+    log::info!("--- end of RAM log");
+}
+
+/// Send what the previous boot logged to the other sinks.
+pub fn dump_previous() {
+    let (start, end) = unsafe { PREVIOUS };
+    let boots = critical_section::with(|_| ram_log().header.boots);
+    if start == end {
+        log::info!("Nothing in the RAM log from the previous boot");
+        return;
+    }
+    log::info!("--- RAM log of boot {}", boots.wrapping_sub(1));
+    dump_range(start, end);
+    log::info!("--- end of RAM log");
+}
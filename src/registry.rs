@@ -0,0 +1,450 @@
+//! The experiments `main` can run, with the hardware each one takes over.
+//!
+//! Which ones run and in what order is given by a comma-separated list of
+//! names, where a trailing `*` matches any suffix and `all` runs everything
+//! in the order of [`EXPERIMENTS`], e.g. `dma,greyscale_*,scaler`. The list
+//! used at boot can be compiled in with the `PICO_EXPERIMENTS` environment
+//! variable:
+//!
+//! ```sh
+//! PICO_EXPERIMENTS="dma,pixel_order" cargo run --release
+//! ```
+//!
+//! Each experiment runs under the watchdog, see `guard`.
+
+use crate::arena;
+use crate::dma_chain;
+use crate::dma_log;
+use crate::dma_random;
+use crate::experiments;
+use crate::experiments::MonochromeColor;
+use crate::golden;
+use crate::guard;
+use crate::lax_dma;
+use crate::pattern::Pattern;
+use crate::report;
+use crate::scaler;
+use crate::uart_log::LineBuffer;
+use core::fmt;
+use core::fmt::Write;
+use rp2040_hal::watchdog::Watchdog;
+use rp2040_pac::PIO0;
+use rp2040_pac::RESETS;
+
+/// The selection compiled in with `PICO_EXPERIMENTS`, everything if not set.
+pub const BUILD_SPEC: &str = match option_env!("PICO_EXPERIMENTS") {
+    Some(spec) => spec,
+    None => "all",
+};
+
+/// Settings for the experiments that take them, changed from the shell.
+#[derive(Copy, Clone)]
+pub struct Params {
+    /// Buffer size in bytes.
+    pub size: usize,
+    pub pattern: Pattern,
+    /// DMA channel, one `dma_log::UART_CHANNELS` leaves free.
+    pub channel: u8,
+    /// Bits per input pixel.
+    pub bpp: u8,
+    /// For the random tests, 0 picks one at every run.
+    pub seed: u32,
+}
+
+impl Params {
+    pub const DEFAULT: Params = Params {
+        size: 256,
+        pattern: Pattern::Counter,
+        channel: 0,
+        bpp: 1,
+        seed: 0,
+    };
+
+    /// Set the parameter `name` from its text.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), &'static str> {
+        match name {
+            "size" => {
+                let size = parse_number(value).ok_or("bad number")? as usize;
+                if size == 0 || size > arena::SIZE || !size.is_multiple_of(4) {
+                    return Err("size must be a multiple of 4 that fits the arena");
+                }
+                self.size = size;
+            }
+            "pattern" => {
+                self.pattern = Pattern::parse(value)
+                    .ok_or("counter, zeros, ones, alt, 0xNN or random[:seed]")?
+            }
+            "channel" => match parse_number(value) {
+                Some(channel)
+                    if channel < lax_dma::CHANNELS as u32
+                        && dma_log::UART_CHANNELS & 1 << channel == 0 =>
+                {
+                    self.channel = channel as u8
+                }
+                _ => return Err("channel must be a DMA channel the UART leaves free"),
+            },
+            "bpp" => match parse_number(value) {
+                Some(bpp @ (1 | 2 | 4)) => self.bpp = bpp as u8,
+                _ => return Err("bpp must be 1, 2 or 4"),
+            },
+            "seed" => self.seed = parse_number(value).ok_or("bad number")?,
+            _ => return Err("size, pattern, channel, bpp or seed"),
+        }
+        Ok(())
+    }
+
+    pub fn input(&self) -> experiments::Input {
+        experiments::Input {
+            size: self.size,
+            pattern: self.pattern,
+        }
+    }
+
+    pub fn log(&self) {
+        log::info!(
+            "size {} pattern {} channel {} bpp {} seed {:#x}",
+            self.size,
+            self.pattern,
+            self.channel,
+            self.bpp,
+            self.seed
+        );
+    }
+}
+
+/// Decimal or `0x` hex.
+pub fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// What the experiments get to run with.
+pub struct Context<'a> {
+    pub resets: &'a mut RESETS,
+    pub watchdog: &'a mut Watchdog,
+    pub sys_hz: u32,
+    pub params: Params,
+}
+
+/// The hardware an experiment uses, it resets the PIO block it runs on.
+#[derive(Copy, Clone)]
+pub struct Resources {
+    pub pio: Option<u8>,
+    /// State machines as a bit mask.
+    pub state_machines: u8,
+    /// DMA channels as a bit mask.
+    pub dma_channels: u16,
+    /// Also the DMA channel of the `channel` parameter.
+    pub uses_channel: bool,
+}
+
+impl Resources {
+    /// With the channel `params` pick, if it uses one.
+    pub fn with_params(&self, params: &Params) -> Resources {
+        let mut resources = *self;
+        if self.uses_channel {
+            resources.dma_channels |= 1 << params.channel;
+        }
+        resources
+    }
+}
+
+pub struct Experiment {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub resources: Resources,
+    pub run: fn(&mut Context),
+}
+
+/// Set bits as a list of their indices.
+struct Bits(u16);
+
+impl fmt::Display for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        for bit in (0..16).filter(|bit| self.0 & 1 << bit != 0) {
+            write!(f, "{}{}", separator, bit)?;
+            separator = ",";
+        }
+        if self.0 == 0 {
+            f.write_str("-")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pio {
+            Some(pio) => write!(f, "PIO{} sm {}", pio, Bits(self.state_machines as u16))?,
+            None => f.write_str("no PIO")?,
+        }
+        write!(f, ", dma {}", Bits(self.dma_channels))
+    }
+}
+
+/// The DMA channels the UART leaves to the experiments.
+const ALL_DMA_CHANNELS: u16 = ((1 << lax_dma::CHANNELS) - 1) & !dma_log::UART_CHANNELS;
+
+fn pio0() -> PIO0 {
+    unsafe { PIO0::steal() }
+}
+
+/// PIO0 SM0 fed and drained by DMA channels 1 and 2, see
+/// `experiments::stream_through_pio`.
+const PIO_STREAM: Resources = Resources {
+    pio: Some(0),
+    state_machines: 0b1,
+    dma_channels: 0b110,
+    uses_channel: false,
+};
+
+fn greyscale(context: &mut Context, color: MonochromeColor) {
+    experiments::test_with_pio_expand_dynamic(
+        pio0(),
+        context.resets,
+        color,
+        golden::ShiftConfig::LSB_FIRST,
+        context.params.input(),
+        context.sys_hz,
+    );
+}
+
+pub const EXPERIMENTS: &[Experiment] = &[
+    Experiment {
+        name: "dma",
+        description: "memory to memory DMA word sizes, byte swaps, increments on every channel",
+        resources: Resources {
+            pio: None,
+            state_machines: 0,
+            dma_channels: ALL_DMA_CHANNELS,
+            uses_channel: false,
+        },
+        run: |_| experiments::run_dma_tests(),
+    },
+    Experiment {
+        name: "dma_chains",
+        description: "chained channels: linear, to self, cycles stopped by abort, fan-in",
+        resources: Resources {
+            pio: None,
+            state_machines: 0,
+            dma_channels: ALL_DMA_CHANNELS,
+            uses_channel: false,
+        },
+        run: |_| dma_chain::run_chain_tests(),
+    },
+    Experiment {
+        name: "dma_copy",
+        description: "memory to memory copy of `size` bytes of `pattern` on `channel`",
+        resources: Resources {
+            pio: None,
+            state_machines: 0,
+            dma_channels: 0,
+            uses_channel: true,
+        },
+        run: |c| experiments::run_dma_copy(c.params.channel, c.params.input()),
+    },
+    Experiment {
+        name: "dma_random",
+        description: "random DMA transfers against a software model on `channel`, `seed`",
+        resources: Resources {
+            pio: None,
+            state_machines: 0,
+            dma_channels: 0,
+            uses_channel: true,
+        },
+        run: |c| dma_random::run(c.params.channel, c.params.seed, dma_random::CASES),
+    },
+    Experiment {
+        name: "invert_twice",
+        description: "two chained SMs inverting `size` bytes of `pattern` twice",
+        resources: Resources {
+            pio: Some(0),
+            state_machines: 0b11,
+            dma_channels: 0b1111,
+            uses_channel: false,
+        },
+        run: |c| {
+            experiments::test_with_pio_invert_twice(pio0(), c.resets, c.params.input(), c.sys_hz)
+        },
+    },
+    Experiment {
+        name: "expand_12times",
+        description: "hand written 1 bpp to RGB444 expansion",
+        resources: PIO_STREAM,
+        run: |c| experiments::test_with_pio_expand_12times(pio0(), c.resets, c.sys_hz),
+    },
+    Experiment {
+        name: "greyscale_1bpp",
+        description: "generated 1 bpp to RGB444 expansion of `size` bytes of `pattern`",
+        resources: PIO_STREAM,
+        run: |c| greyscale(c, MonochromeColor::Bpp1),
+    },
+    Experiment {
+        name: "greyscale_2bpp",
+        description: "generated 2 bpp to RGB444 expansion of `size` bytes of `pattern`",
+        resources: PIO_STREAM,
+        run: |c| greyscale(c, MonochromeColor::Bpp2),
+    },
+    Experiment {
+        name: "greyscale_4bpp",
+        description: "generated 4 bpp to RGB444 expansion of `size` bytes of `pattern`",
+        resources: PIO_STREAM,
+        run: |c| greyscale(c, MonochromeColor::Bpp4),
+    },
+    Experiment {
+        name: "greyscale",
+        description: "generated `bpp` to RGB444 expansion of `size` bytes of `pattern`",
+        resources: PIO_STREAM,
+        run: |c| {
+            let color = match c.params.bpp {
+                2 => MonochromeColor::Bpp2,
+                4 => MonochromeColor::Bpp4,
+                _ => MonochromeColor::Bpp1,
+            };
+            greyscale(c, color);
+        },
+    },
+    Experiment {
+        name: "pio_programs",
+        description: "every program of the generator registry",
+        resources: PIO_STREAM,
+        run: |c| experiments::test_with_pio_programs(pio0, c.resets, c.sys_hz),
+    },
+    Experiment {
+        name: "pixel_order",
+        description: "where each input pixel lands, LSB and MSB first",
+        resources: PIO_STREAM,
+        run: |c| {
+            for color in [
+                MonochromeColor::Bpp1,
+                MonochromeColor::Bpp2,
+                MonochromeColor::Bpp4,
+            ] {
+                for shift in [
+                    golden::ShiftConfig::LSB_FIRST,
+                    golden::ShiftConfig::MSB_FIRST,
+                ] {
+                    experiments::test_pixel_order(pio0, c.resets, color, shift);
+                }
+            }
+        },
+    },
+    Experiment {
+        name: "scaler",
+        description: "DMA driven line scaler at 1, 2 and 4 bpp",
+        resources: Resources {
+            pio: Some(0),
+            state_machines: 0b1,
+            dma_channels: 0b111,
+            uses_channel: false,
+        },
+        run: |c| {
+            for (bpp, h_scale, v_scale) in [(1, 3, 2), (2, 2, 2), (4, 2, 3)] {
+                experiments::test_with_pio_scale(
+                    pio0(),
+                    c.resets,
+                    scaler::ScaleConfig {
+                        bpp,
+                        width: 32,
+                        height: 4,
+                        h_scale,
+                        v_scale,
+                    },
+                    c.sys_hz,
+                );
+            }
+        },
+    },
+];
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// Log the experiments and what they use with `params`.
+pub fn list(params: &Params) {
+    for e in EXPERIMENTS {
+        let mut resources = LineBuffer::<48>::new();
+        write!(resources, "{}", e.resources.with_params(params)).ok();
+        log::info!(
+            "{:16} {:24} {}",
+            e.name,
+            core::str::from_utf8(resources.as_bytes()).unwrap_or_default(),
+            e.description
+        );
+    }
+}
+
+/// Run the experiments selected by `spec`, see the module docs. Returns
+/// how many ran.
+pub fn run(spec: &str, context: &mut Context) -> usize {
+    run_from(spec, context, 0)
+}
+
+/// Like `run`, but leave out the first `skip` experiments `spec` selects.
+pub fn run_from(spec: &str, context: &mut Context, skip: usize) -> usize {
+    let mut step = 0;
+    let mut ran = 0;
+    for pattern in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let pattern = if pattern == "all" { "*" } else { pattern };
+        let mut found = false;
+        for (index, e) in EXPERIMENTS
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| matches(pattern, e.name))
+        {
+            found = true;
+            step += 1;
+            if step <= skip {
+                continue;
+            }
+            log::info!(
+                "=== {} ({})",
+                e.name,
+                e.resources.with_params(&context.params)
+            );
+            guard::arm(
+                context.watchdog,
+                guard::Position::new(spec, step - 1, index),
+            );
+            (e.run)(context);
+            guard::disarm(context.watchdog);
+            ran += 1;
+        }
+        if !found {
+            log::error!("No experiment matches {:?}, see the list", pattern);
+        }
+    }
+    ran
+}
+
+/// Report the experiment the previous boot hung or panicked in as failed.
+/// Returns how many experiments of `spec` to skip to go on after it.
+pub fn recover(spec: &str, watchdog: &mut Watchdog) -> usize {
+    let Some((at, what)) = guard::take_previous(watchdog) else {
+        return 0;
+    };
+    let name = EXPERIMENTS.get(at.index as usize).map_or("?", |e| e.name);
+    log::error!("!!! {} {} on the previous boot", name, what);
+    report::result(&report::TestResult {
+        name,
+        channel: None,
+        duration_us: 0,
+        status: report::Status::Fail,
+        mismatches: 0,
+        detail: what,
+    });
+
+    // Not from `spec` if it was run from the shell.
+    if !at.is_in(spec) {
+        return 0;
+    }
+    log::warn!("Going on after {}", name);
+    at.step as usize + 1
+}
@@ -0,0 +1,268 @@
+//! Machine-readable test results, next to the human log.
+//!
+//! Every result is one raw line on the log sinks, written without the log
+//! prefix so a harness can pick them out of the serial capture:
+//!
+//! - TAP: `ok 3 - dma_simple ch5 # 12 us`, failures followed by a YAML
+//!   block with the details, and the plan `1..N` with the summary.
+//! - JSON lines: `{"type":"result","name":"dma_simple",...}` and a final
+//!   `{"type":"summary",...}`.
+//!
+//! The format is compiled in with the `PICO_REPORT` environment variable,
+//! `tap` (default) or `json`.
+
+use crate::uart_log::LineBuffer;
+use core::cell::Cell;
+use core::fmt::Write;
+use critical_section::Mutex;
+
+const LINE_SIZE: usize = 256;
+
+/// The format compiled in with `PICO_REPORT`.
+pub const BUILD_FORMAT: &str = match option_env!("PICO_REPORT") {
+    Some(format) => format,
+    None => "tap",
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Tap,
+    JsonLines,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "tap" => Some(Format::Tap),
+            "json" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Skip => "skip",
+        }
+    }
+}
+
+pub struct TestResult<'a> {
+    pub name: &'a str,
+    /// The DMA channel the test ran on, if it's about one.
+    pub channel: Option<u8>,
+    pub duration_us: u64,
+    pub status: Status,
+    /// Mismatching bytes, see `hexdump::diff`.
+    pub mismatches: usize,
+    /// What went wrong, empty if nothing did.
+    pub detail: &'a str,
+}
+
+impl TestResult<'_> {
+    /// Pass unless there are mismatches.
+    pub fn check(
+        name: &str,
+        channel: Option<u8>,
+        duration_us: u64,
+        mismatches: usize,
+    ) -> TestResult<'_> {
+        TestResult {
+            name,
+            channel,
+            duration_us,
+            status: if mismatches == 0 {
+                Status::Pass
+            } else {
+                Status::Fail
+            },
+            mismatches,
+            detail: if mismatches == 0 {
+                ""
+            } else {
+                "output differs from expected"
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Counts {
+    passed: u32,
+    failed: u32,
+    skipped: u32,
+}
+
+impl Counts {
+    fn total(&self) -> u32 {
+        self.passed + self.failed + self.skipped
+    }
+}
+
+static FORMAT: Mutex<Cell<Format>> = Mutex::new(Cell::new(Format::Tap));
+static COUNTS: Mutex<Cell<Counts>> = Mutex::new(Cell::new(Counts {
+    passed: 0,
+    failed: 0,
+    skipped: 0,
+}));
+
+/// Send `line`, always ending it, even if it had to be truncated.
+fn emit(mut line: LineBuffer<LINE_SIZE>) {
+    line.finish("\r\n");
+    crate::uart_log::write_record(line.as_bytes());
+}
+
+/// JSON string contents, without the quotes.
+struct JsonEscaped<'a>(&'a str);
+
+impl core::fmt::Display for JsonEscaped<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pick the format and start the report.
+pub fn init(format: &str) {
+    let format = Format::parse(format).unwrap_or_else(|| {
+        log::error!("Unknown report format {:?}, using TAP", format);
+        Format::Tap
+    });
+    critical_section::with(|cs| FORMAT.borrow(cs).set(format));
+
+    let mut line = LineBuffer::<LINE_SIZE>::new();
+    match format {
+        Format::Tap => write!(line, "TAP version 13"),
+        Format::JsonLines => write!(line, "{{\"type\":\"start\",\"version\":1}}"),
+    }
+    .ok();
+    emit(line);
+}
+
+pub fn result(result: &TestResult) {
+    // Progress, see `guard`.
+    crate::guard::feed();
+
+    let (format, number) = critical_section::with(|cs| {
+        let counts = COUNTS.borrow(cs);
+        let mut c = counts.get();
+        match result.status {
+            Status::Pass => c.passed += 1,
+            Status::Fail => c.failed += 1,
+            Status::Skip => c.skipped += 1,
+        }
+        counts.set(c);
+        (FORMAT.borrow(cs).get(), c.total())
+    });
+
+    let mut line = LineBuffer::<LINE_SIZE>::new();
+    match format {
+        Format::Tap => {
+            let ok = if result.status == Status::Fail {
+                "not ok"
+            } else {
+                "ok"
+            };
+            write!(line, "{} {} - {}", ok, number, result.name).ok();
+            if let Some(channel) = result.channel {
+                write!(line, " ch{}", channel).ok();
+            }
+            match result.status {
+                Status::Skip => write!(line, " # SKIP {}", result.detail),
+                _ => write!(line, " # {} us", result.duration_us),
+            }
+            .ok();
+            emit(line);
+
+            if result.status == Status::Fail {
+                let mut yaml = LineBuffer::<LINE_SIZE>::new();
+                write!(
+                    yaml,
+                    "  ---\r\n  duration_us: {}\r\n  mismatches: {}\r\n  detail: \"{}\"\r\n  ...",
+                    result.duration_us,
+                    result.mismatches,
+                    JsonEscaped(result.detail)
+                )
+                .ok();
+                emit(yaml);
+            }
+        }
+        Format::JsonLines => {
+            write!(
+                line,
+                "{{\"type\":\"result\",\"name\":\"{}\",\"channel\":",
+                JsonEscaped(result.name)
+            )
+            .ok();
+            match result.channel {
+                Some(channel) => write!(line, "{}", channel),
+                None => write!(line, "null"),
+            }
+            .ok();
+            write!(
+                line,
+                ",\"duration_us\":{},\"status\":\"{}\",\"mismatches\":{},\"detail\":\"{}\"}}",
+                result.duration_us,
+                result.status.name(),
+                result.mismatches,
+                JsonEscaped(result.detail)
+            )
+            .ok();
+            emit(line);
+        }
+    }
+}
+
+/// Finish the report with the counts. Returns `true` if nothing failed.
+pub fn summary() -> bool {
+    let (format, counts) =
+        critical_section::with(|cs| (FORMAT.borrow(cs).get(), COUNTS.borrow(cs).get()));
+
+    let mut line = LineBuffer::<LINE_SIZE>::new();
+    match format {
+        Format::Tap => write!(
+            line,
+            "1..{}\r\n# pass {} fail {} skip {}",
+            counts.total(),
+            counts.passed,
+            counts.failed,
+            counts.skipped
+        ),
+        Format::JsonLines => write!(
+            line,
+            "{{\"type\":\"summary\",\"total\":{},\"passed\":{},\"failed\":{},\"skipped\":{}}}",
+            counts.total(),
+            counts.passed,
+            counts.failed,
+            counts.skipped
+        ),
+    }
+    .ok();
+    emit(line);
+
+    log::info!(
+        "*** {} tests: {} passed, {} failed, {} skipped",
+        counts.total(),
+        counts.passed,
+        counts.failed,
+        counts.skipped
+    );
+    counts.failed == 0
+}
@@ -0,0 +1,42 @@
+//! Small seeded PRNG for test data, the same seed gives the same sequence
+//! on every build.
+
+/// SplitMix32: any seed works, including 0.
+#[derive(Clone)]
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        Rng(seed)
+    }
+
+    /// A seed that differs from boot to boot, for when none is given.
+    pub fn seed_from_time() -> u32 {
+        let us = crate::time::time_us64();
+        Rng::new(us as u32 ^ (us >> 32) as u32).next_u32()
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9);
+        let mut z = self.0;
+        z = (z ^ (z >> 16)).wrapping_mul(0x85eb_ca6b);
+        z = (z ^ (z >> 13)).wrapping_mul(0xc2b2_ae35);
+        z ^ (z >> 16)
+    }
+
+    /// In `0..n`, `n` must not be 0.
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u32() & 1 != 0
+    }
+
+    pub fn fill(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
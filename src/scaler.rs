@@ -0,0 +1,239 @@
+//! Integer upscaling of framebuffers in the PIO/DMA path.
+//!
+//! Every pixel is repeated horizontally by the `replicate` program on
+//! PIO0 SM0. Every line is re-read vertically by DMA:
+//!
+//! | DMA Channel | Source (Read Address)      | Destination (Write Address)  | Chains to |
+//! |-------------|----------------------------|------------------------------|-----------|
+//! | DMA 0 (CTL) | Line address list          | DMA 1 read address trigger   | -         |
+//! | DMA 1 (TX)  | Framebuffer line           | PIO TX FIFO (PIO0_TXF_SM0)   | DMA 0     |
+//! | DMA 2 (RX)  | PIO RX FIFO (PIO0_RXF_SM0) | Output buffer                | -         |
+//!
+//! The list holds the address of every line `v_scale` times and ends with
+//! a zero: writing zero to a trigger register doesn't start the channel,
+//! and that stops the chain.
+
+use crate::golden;
+use crate::lax_dma::Config;
+use crate::lax_dma::Destination;
+use crate::lax_dma::LaxDmaWrite;
+use crate::lax_dma::Source;
+use crate::lax_dma::TxReq;
+use crate::lax_dma::TxSize;
+use crate::pio_programs;
+use crate::time;
+use rp2040_hal::dma;
+use rp2040_hal::pio::PIOExt;
+use rp2040_pac::PIO0;
+use rp2040_pac::RESETS;
+
+/// Far longer than any frame here should take.
+const TIMEOUT: time::Duration = time::Duration::millis(100);
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum ScaleError {
+    Program(pio_programs::ProgramError),
+    /// Lines must be whole 32-bit words for DMA.
+    LineNotWordAligned,
+    /// Zero width, height or scale factor.
+    Empty,
+    BufferTooSmall,
+    /// The DMA transfers didn't finish in time.
+    TimedOut,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScaleConfig {
+    pub bpp: u8,
+    /// Pixels per input line.
+    pub width: usize,
+    /// Input lines.
+    pub height: usize,
+    pub h_scale: u8,
+    pub v_scale: u8,
+}
+
+impl ScaleConfig {
+    pub fn line_bytes(&self) -> usize {
+        self.width * self.bpp as usize / 8
+    }
+
+    pub fn input_bytes(&self) -> usize {
+        self.line_bytes() * self.height
+    }
+
+    pub fn output_line_bytes(&self) -> usize {
+        self.line_bytes() * self.h_scale as usize
+    }
+
+    pub fn output_bytes(&self) -> usize {
+        self.output_line_bytes() * self.height * self.v_scale as usize
+    }
+
+    /// Expected output with `shift`, see `golden`: every line replicated
+    /// horizontally and then repeated `v_scale` times. Returns the number
+    /// of bytes produced.
+    pub fn expected(&self, input: &[u8], shift: &golden::ShiftConfig, output: &mut [u8]) -> usize {
+        let mut written = 0;
+        for line in input.chunks_exact(self.line_bytes()).take(self.height) {
+            for _ in 0..self.v_scale {
+                let Some(dst) = output.get_mut(written..written + self.output_line_bytes()) else {
+                    return written;
+                };
+                written += golden::replicate(line, self.bpp, self.h_scale, shift, dst);
+            }
+        }
+
+        written
+    }
+
+    /// Entries of the line address list including the terminating zero.
+    pub fn control_entries(&self) -> usize {
+        self.height * self.v_scale as usize + 1
+    }
+
+    fn program_spec(&self) -> pio_programs::ProgramSpec {
+        pio_programs::ProgramSpec::BitReplicate {
+            bpp: self.bpp,
+            times: self.h_scale,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ScaleError> {
+        self.program_spec()
+            .validate()
+            .map_err(ScaleError::Program)?;
+        if self.width == 0 || self.height == 0 || self.v_scale == 0 {
+            return Err(ScaleError::Empty);
+        }
+        if !(self.width * self.bpp as usize).is_multiple_of(32) {
+            return Err(ScaleError::LineNotWordAligned);
+        }
+
+        Ok(())
+    }
+}
+
+/// Upscale `input` into `output`, using `control` for the line address list.
+/// Returns the time the DMA transfers took in microseconds.
+pub fn scale(
+    pio: PIO0,
+    resets: &mut RESETS,
+    config: &ScaleConfig,
+    shift: &golden::ShiftConfig,
+    input: &[u8],
+    control: &mut [u32],
+    output: &mut [u8],
+) -> Result<u64, ScaleError> {
+    config.validate()?;
+    if input.len() < config.input_bytes()
+        || output.len() < config.output_bytes()
+        || control.len() < config.control_entries()
+    {
+        return Err(ScaleError::BufferTooSmall);
+    }
+
+    let line_bytes = config.line_bytes();
+    let lines = input.chunks_exact(line_bytes).take(config.height);
+    let repeated = lines.flat_map(|line| core::iter::repeat_n(line, config.v_scale as usize));
+    for (entry, line) in control.iter_mut().zip(repeated) {
+        *entry = line.as_ptr() as u32;
+    }
+    control[config.control_entries() - 1] = 0;
+
+    let program = config
+        .program_spec()
+        .generate()
+        .map_err(ScaleError::Program)?;
+    let (mut pio, sm0, _, _, _) = pio.split(resets);
+    let installed_pio = pio.install(&program).unwrap();
+    let (sm, rx, tx) = rp2040_hal::pio::PIOBuilder::from_installed_program(installed_pio)
+        .out_shift_direction(shift.out_shift.into())
+        .in_shift_direction(shift.in_shift.into())
+        .pull_threshold(shift.pull_threshold & 0x1f)
+        .push_threshold(shift.push_threshold & 0x1f)
+        .autopull(true)
+        .autopush(true)
+        .build(sm0);
+    sm.start();
+
+    let txf = tx.fifo_address();
+    let rxf = rx.fifo_address();
+
+    // This DMA channel drains the PIO state machine's RX FIFO into the output buffer.
+    let dma2 = LaxDmaWrite::new::<dma::CH2>(Config {
+        high_priority: false,
+        word_size: TxSize::_32bit,
+        source: Source {
+            address: rxf.cast(),
+            increment: false,
+        },
+        destination: Destination {
+            address: output.as_mut_ptr(),
+            increment: true,
+        },
+        tx_count: config.output_bytes() as u32 / 4,
+        tx_req: TxReq::Pio0Rx0,
+        byte_swap: shift.byte_swap_out,
+        start: true,
+    });
+
+    // This DMA channel feeds one line to the PIO state machine's TX FIFO
+    // each time DMA 0 writes its read address trigger, then hands control
+    // back to DMA 0 for the next line.
+    let dma1 = LaxDmaWrite::new_chained::<dma::CH1, dma::CH0>(Config {
+        high_priority: false,
+        word_size: TxSize::_32bit,
+        source: Source {
+            address: core::ptr::null(),
+            increment: true,
+        },
+        destination: Destination {
+            address: txf.cast_mut().cast(),
+            increment: false,
+        },
+        tx_count: line_bytes as u32 / 4,
+        tx_req: TxReq::Pio0Tx0,
+        byte_swap: shift.byte_swap_in,
+        start: false,
+    });
+
+    // This DMA channel walks the line address list one entry per trigger.
+    let dma0 = LaxDmaWrite::new::<dma::CH0>(Config {
+        high_priority: false,
+        word_size: TxSize::_32bit,
+        source: Source {
+            address: control.as_ptr().cast(),
+            increment: true,
+        },
+        destination: Destination {
+            address: dma1.read_trig_addr().cast_mut().cast(),
+            increment: false,
+        },
+        tx_count: 1,
+        tx_req: TxReq::Permanent,
+        byte_swap: false,
+        start: false,
+    });
+
+    let start = time::time_us64();
+    dma0.trigger();
+    let done = dma2.wait_timeout(TIMEOUT);
+    let elapsed_us = time::time_us64() - start;
+    if done.is_err() {
+        // `dma1` restarts `dma0`, which an abort could set off, see
+        // `LaxDmaWrite::unchain`.
+        dma1.unchain();
+        dma0.unchain();
+        dma0.abort();
+        dma1.abort();
+        return Err(ScaleError::TimedOut);
+    }
+
+    dma1.wait_timeout(TIMEOUT)
+        .and_then(|()| dma0.wait_timeout(TIMEOUT))
+        .map_err(|_| ScaleError::TimedOut)?;
+
+    Ok(elapsed_us)
+}
@@ -0,0 +1,224 @@
+//! Command console on the log UART.
+//!
+//! What's typed arrives by DMA, see `dma_log::DmaUartReader`, and is handled
+//! by `poll` from the main loop. It's echoed through the log sinks, and the
+//! commands answer with log records. Backspace, Ctrl-U (clear the line) and
+//! Ctrl-C work; `help` lists the commands.
+
+use crate::dma_log::DmaUartReader;
+use crate::lax_dma;
+use crate::lax_pio::PioBlock;
+use crate::log_format::LineFormat;
+use crate::profile;
+use crate::ram_log;
+use crate::registry;
+use crate::registry::parse_number;
+use crate::report;
+use crate::uart_log;
+
+const LINE_SIZE: usize = 96;
+const PROMPT: &[u8] = b"> ";
+
+/// Most words `peek` reads at once.
+const MAX_PEEK: u32 = 64;
+
+const HELP: &[(&str, &str)] = &[
+    ("help", "this list"),
+    ("list", "the experiments and the hardware they use"),
+    ("run <names>", "run experiments, e.g. `run dma,greyscale_*`"),
+    ("params", "show the experiment parameters"),
+    (
+        "set <name> <value>",
+        "set size, pattern, channel, bpp or seed",
+    ),
+    ("peek <addr> [words]", "read memory, unchecked"),
+    ("poke <addr> <value>", "write a word, unchecked"),
+    ("dma [channel]", "DMA channel registers, all by default"),
+    ("pio [0|1]", "PIO block and state machine registers"),
+    ("log [filter]", "show or set the log filter"),
+    ("format [fields]", "show or set the log line format"),
+    ("ramlog", "send the RAM log"),
+    ("profile [reset]", "cycle count statistics"),
+    ("results", "test result summary"),
+];
+
+pub struct Shell {
+    reader: DmaUartReader,
+    line: [u8; LINE_SIZE],
+    len: usize,
+    /// To take CR LF as one line ending.
+    after_cr: bool,
+}
+
+fn echo(bytes: &[u8]) {
+    uart_log::write_record(bytes);
+}
+
+impl Shell {
+    pub fn new(reader: DmaUartReader) -> Self {
+        echo(PROMPT);
+        Shell {
+            reader,
+            line: [0; LINE_SIZE],
+            len: 0,
+            after_cr: false,
+        }
+    }
+
+    /// Handle whatever was typed since the last call.
+    pub fn poll(&mut self, context: &mut registry::Context) {
+        let mut buf = [0u8; 32];
+        let mut any = false;
+        loop {
+            let n = self.reader.read(&mut buf);
+            if n == 0 {
+                break;
+            }
+            any = true;
+            for &b in &buf[..n] {
+                self.input(b, context);
+            }
+        }
+        // The DMA transport only moves on when written to.
+        if any {
+            log::logger().flush();
+        }
+    }
+
+    fn input(&mut self, b: u8, context: &mut registry::Context) {
+        let after_cr = core::mem::replace(&mut self.after_cr, b == b'\r');
+        match b {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                echo(b"\r\n");
+                let line = self.line;
+                let len = core::mem::take(&mut self.len);
+                // Only printable ASCII gets in.
+                let line = core::str::from_utf8(&line[..len]).unwrap_or_default();
+                if !line.trim().is_empty() {
+                    execute(line, context);
+                }
+                echo(PROMPT);
+            }
+            0x08 | 0x7f => {
+                if self.len > 0 {
+                    self.len -= 1;
+                    echo(b"\x08 \x08");
+                }
+            }
+            // Ctrl-C, Ctrl-U
+            0x03 | 0x15 => {
+                self.len = 0;
+                echo(if b == 0x03 { b"^C\r\n" } else { b"\r\n" });
+                echo(PROMPT);
+            }
+            0x20..=0x7e if self.len < LINE_SIZE => {
+                self.line[self.len] = b;
+                self.len += 1;
+                echo(&[b]);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn execute(line: &str, context: &mut registry::Context) {
+    let line = line.trim();
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut args = rest.split_whitespace();
+
+    match command {
+        "help" => {
+            for (usage, help) in HELP {
+                log::info!("{:20} {}", usage, help);
+            }
+        }
+        "list" => registry::list(&context.params),
+        "run" if !rest.is_empty() => {
+            let ran = registry::run(rest, context);
+            log::info!("Ran {} experiments", ran);
+        }
+        "params" => context.params.log(),
+        "set" => match (args.next(), args.next()) {
+            (Some(name), Some(value)) => match context.params.set(name, value) {
+                Ok(()) => context.params.log(),
+                Err(e) => log::error!("{}: {}", name, e),
+            },
+            _ => log::error!("usage: set <name> <value>"),
+        },
+        "peek" => {
+            let addr = args.next().and_then(parse_number);
+            let words = args.next().map_or(Some(1), parse_number);
+            match (addr, words) {
+                (Some(addr), Some(words)) if addr % 4 == 0 => peek(addr, words.min(MAX_PEEK)),
+                _ => log::error!("usage: peek <word aligned addr> [words]"),
+            }
+        }
+        "poke" => match (
+            args.next().and_then(parse_number),
+            args.next().and_then(parse_number),
+        ) {
+            (Some(addr), Some(value)) if addr % 4 == 0 => {
+                unsafe { (addr as *mut u32).write_volatile(value) };
+                log::info!("{:08x} <- {:08x}", addr, value);
+            }
+            _ => log::error!("usage: poke <word aligned addr> <value>"),
+        },
+        "dma" => match args.next().map(parse_number) {
+            None => (0..lax_dma::CHANNELS as usize)
+                .for_each(|i| lax_dma::log_channel(log::Level::Info, i)),
+            Some(Some(i)) if i < lax_dma::CHANNELS as u32 => {
+                lax_dma::log_channel(log::Level::Info, i as usize)
+            }
+            _ => log::error!("usage: dma [0..11]"),
+        },
+        "pio" => match args.next() {
+            None => {
+                PioBlock::Pio0.log_state(log::Level::Info);
+                PioBlock::Pio1.log_state(log::Level::Info);
+            }
+            Some("0") => PioBlock::Pio0.log_state(log::Level::Info),
+            Some("1") => PioBlock::Pio1.log_state(log::Level::Info),
+            _ => log::error!("usage: pio [0|1]"),
+        },
+        "log" if rest.is_empty() => uart_log::log_filter(),
+        "log" => match uart_log::set_log_filter(rest) {
+            Ok(()) => uart_log::log_filter(),
+            Err(e) => log::error!("Bad log filter: {:?}", e),
+        },
+        "format" if rest.is_empty() => uart_log::log_format(),
+        "format" => match LineFormat::parse(rest) {
+            Ok(format) => {
+                uart_log::set_log_format(format);
+                format.log();
+            }
+            Err(e) => log::error!("Bad log format: {:?}", e),
+        },
+        "ramlog" => ram_log::dump(),
+        "profile" => match args.next() {
+            None => profile::report(),
+            Some("reset") => profile::reset(),
+            _ => log::error!("usage: profile [reset]"),
+        },
+        "results" => {
+            report::summary();
+        }
+        _ => log::error!("Unknown command {:?}, try help", line),
+    }
+}
+
+/// Log `words` words from `addr`, four to a line.
+fn peek(addr: u32, words: u32) {
+    // Stop at the top of the address space instead of wrapping around.
+    let words = words.min((u32::MAX - addr) / 4 + 1);
+    for line in (0..words).step_by(4) {
+        let mut values = [0u32; 4];
+        let n = (words - line).min(4) as usize;
+        for (i, v) in values[..n].iter_mut().enumerate() {
+            let p = (addr + (line + i as u32) * 4) as *const u32;
+            *v = unsafe { p.read_volatile() };
+        }
+        log::info!("{:08x}: {:08x?}", addr + line * 4, &values[..n]);
+    }
+}
@@ -1,10 +1,244 @@
+//! The 64-bit microsecond TIMER: readings as fugit `Instant`s, busy-wait
+//! delays, deadlines for polling loops, and the four hardware alarms.
+//!
+//! An alarm calls a plain `fn()` from its interrupt handler, once or
+//! periodically. The hardware only compares the low 32 bits of the
+//! counter, so alarms further out than ~71 minutes are re-armed until
+//! they're due.
+
+use core::cell::Cell;
+use critical_section::Mutex;
+use rp2040_pac::interrupt;
+
+pub type Instant = fugit::TimerInstantU64<1_000_000>;
+pub type Duration = fugit::TimerDurationU64<1_000_000>;
+
+#[allow(dead_code)]
 pub fn time_us() -> u32 {
     unsafe { (*rp2040_pac::TIMER::PTR).timerawl().read().bits() }
 }
 
+/// Reads the raw registers: `TIMELR` latches `TIMEHR` for whoever reads it
+/// first, so an interrupt handler or the other core reading the time in
+/// between would break the pair.
 pub fn time_us64() -> u64 {
-    unsafe {
-        (*rp2040_pac::TIMER::PTR).timelr().read().bits() as u64
-            | (((*rp2040_pac::TIMER::PTR).timehr().read().bits() as u64) << 32)
+    let timer = unsafe { &*rp2040_pac::TIMER::PTR };
+    let mut hi = timer.timerawh().read().bits();
+    loop {
+        let lo = timer.timerawl().read().bits();
+        let next_hi = timer.timerawh().read().bits();
+        if next_hi == hi {
+            return (hi as u64) << 32 | lo as u64;
+        }
+        hi = next_hi;
+    }
+}
+
+pub fn now() -> Instant {
+    Instant::from_ticks(time_us64())
+}
+
+/// Spin for `duration`.
+pub fn delay(duration: Duration) {
+    let deadline = Deadline::after(duration);
+    while !deadline.expired() {}
+}
+
+/// Waiting for something took longer than allowed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimedOut;
+
+/// A point in time to give up at.
+#[derive(Copy, Clone, Debug)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(duration: Duration) -> Self {
+        Deadline(now() + duration)
+    }
+
+    pub fn expired(&self) -> bool {
+        now() >= self.0
+    }
+
+    #[allow(dead_code)]
+    pub fn remaining(&self) -> Duration {
+        self.0
+            .checked_duration_since(now())
+            .unwrap_or(Duration::from_ticks(0))
+    }
+
+    /// Poll `done` until it returns `true` or the deadline passes. It's
+    /// checked once more after the deadline, so a slow poll doesn't turn
+    /// into a spurious timeout.
+    pub fn wait_for(&self, mut done: impl FnMut() -> bool) -> Result<(), TimedOut> {
+        loop {
+            let expired = self.expired();
+            if done() {
+                return Ok(());
+            }
+            if expired {
+                return Err(TimedOut);
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Alarm {
+    Alarm0 = 0,
+    Alarm1 = 1,
+    Alarm2 = 2,
+    Alarm3 = 3,
+}
+
+const ALARMS: usize = 4;
+
+#[derive(Copy, Clone)]
+struct Scheduled {
+    at: u64,
+    /// Re-armed this much later after firing if set.
+    period: Option<u64>,
+    callback: fn(),
+}
+
+static SCHEDULED: Mutex<Cell<[Option<Scheduled>; ALARMS]>> = Mutex::new(Cell::new([None; ALARMS]));
+
+fn timer() -> &'static rp2040_pac::timer::RegisterBlock {
+    unsafe { &*rp2040_pac::TIMER::PTR }
+}
+
+const IRQS: [rp2040_pac::Interrupt; ALARMS] = [
+    rp2040_pac::Interrupt::TIMER_IRQ_0,
+    rp2040_pac::Interrupt::TIMER_IRQ_1,
+    rp2040_pac::Interrupt::TIMER_IRQ_2,
+    rp2040_pac::Interrupt::TIMER_IRQ_3,
+];
+
+/// Set the comparator for `at`. If that went by already, the interrupt
+/// is forced, as the comparator would only match after wrapping around.
+fn arm(alarm: usize, at: u64) {
+    let timer = timer();
+    let target = at as u32;
+    match alarm {
+        0 => timer.alarm0().write(|w| unsafe { w.bits(target) }),
+        1 => timer.alarm1().write(|w| unsafe { w.bits(target) }),
+        2 => timer.alarm2().write(|w| unsafe { w.bits(target) }),
+        _ => timer.alarm3().write(|w| unsafe { w.bits(target) }),
+    };
+    if time_us64() >= at {
+        timer
+            .intf()
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << alarm) });
     }
 }
+
+/// Call `callback` from the alarm's interrupt at `at`, and then every
+/// `period` if given. Replaces whatever the alarm was set for.
+fn schedule(alarm: Alarm, at: Instant, period: Option<Duration>, callback: fn()) {
+    let alarm = alarm as usize;
+    critical_section::with(|cs| {
+        let scheduled = SCHEDULED.borrow(cs);
+        let mut all = scheduled.get();
+        all[alarm] = Some(Scheduled {
+            at: at.ticks(),
+            period: period.map(|p| p.ticks().max(1)),
+            callback,
+        });
+        scheduled.set(all);
+
+        timer()
+            .inte()
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << alarm) });
+        arm(alarm, at.ticks());
+    });
+    unsafe { cortex_m::peripheral::NVIC::unmask(IRQS[alarm]) };
+}
+
+/// Call `callback` once, `delay` from now.
+#[allow(dead_code)]
+pub fn schedule_after(alarm: Alarm, delay: Duration, callback: fn()) {
+    schedule(alarm, now() + delay, None, callback);
+}
+
+/// Call `callback` every `period`, starting one period from now.
+pub fn schedule_every(alarm: Alarm, period: Duration, callback: fn()) {
+    schedule(alarm, now() + period, Some(period), callback);
+}
+
+#[allow(dead_code)]
+pub fn cancel(alarm: Alarm) {
+    let alarm = alarm as usize;
+    critical_section::with(|cs| {
+        let scheduled = SCHEDULED.borrow(cs);
+        let mut all = scheduled.get();
+        all[alarm] = None;
+        scheduled.set(all);
+
+        let timer = timer();
+        timer.armed().write(|w| unsafe { w.bits(1 << alarm) });
+        timer
+            .inte()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << alarm)) });
+        timer
+            .intf()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << alarm)) });
+        timer.intr().write(|w| unsafe { w.bits(1 << alarm) });
+    });
+}
+
+fn on_alarm(alarm: usize) {
+    let callback = critical_section::with(|cs| {
+        let timer = timer();
+        timer
+            .intf()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << alarm)) });
+        timer.intr().write(|w| unsafe { w.bits(1 << alarm) });
+
+        let scheduled = SCHEDULED.borrow(cs);
+        let mut all = scheduled.get();
+        let mut entry = all[alarm]?;
+        let now = time_us64();
+        if now < entry.at {
+            // Only the low 32 bits matched.
+            arm(alarm, entry.at);
+            return None;
+        }
+
+        all[alarm] = entry.period.map(|period| {
+            // Skip the periods missed rather than firing for each.
+            let missed = (now - entry.at) / period;
+            entry.at += (missed + 1) * period;
+            arm(alarm, entry.at);
+            entry
+        });
+        scheduled.set(all);
+        Some(entry.callback)
+    });
+
+    // Outside the critical section, so the callback can log.
+    if let Some(callback) = callback {
+        callback();
+    }
+}
+
+#[interrupt]
+fn TIMER_IRQ_0() {
+    on_alarm(0);
+}
+
+#[interrupt]
+fn TIMER_IRQ_1() {
+    on_alarm(1);
+}
+
+#[interrupt]
+fn TIMER_IRQ_2() {
+    on_alarm(2);
+}
+
+#[interrupt]
+fn TIMER_IRQ_3() {
+    on_alarm(3);
+}
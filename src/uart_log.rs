@@ -1,71 +1,234 @@
+//! Logging to UARTs or anything else implementing `LogSink`.
+//!
+//! Records can come from any context on either core: they are formatted on
+//! the caller's stack and written to one sink after the other. A sink is
+//! taken out of its slot in a critical section, which on the RP2040 masks
+//! interrupts and takes a hardware spinlock, but written to and flushed
+//! outside it, so a slow sink doesn't hold off interrupts. A sink the other
+//! core is using is waited for. One in use on the same core, i.e. by a
+//! record logged from a sink or from an interrupt, skips the record.
+
+use crate::log_filter::Filter;
+use crate::log_filter::FilterError;
+use crate::log_format::LineFormat;
+use crate::log_format::Prefix;
+use crate::log_sink::LogSink;
+use core::cell::Cell;
 use core::cell::RefCell;
 use core::fmt::Write;
-use rp2040_hal::gpio::FunctionUart;
+use critical_section::Mutex;
+use rp2040_hal::uart::Enabled;
+use rp2040_hal::uart::UartDevice;
 use rp2040_hal::uart::UartPeripheral;
+use rp2040_hal::uart::ValidUartPinout;
+
+/// Longest record, longer ones are truncated and end with `~`.
+const LINE_SIZE: usize = 512;
+
+/// Fixed-size buffer to format a record into.
+pub struct LineBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Make room for `tail` at the end, marking the line as truncated
+    /// if it had to be shortened.
+    pub fn finish(&mut self, tail: &str) {
+        let room = N - tail.len();
+        if self.truncated || self.len > room {
+            self.len = self.len.min(room - 1);
+            self.buf[self.len] = b'~';
+            self.len += 1;
+        }
+        self.buf[self.len..self.len + tail.len()].copy_from_slice(tail.as_bytes());
+        self.len += tail.len();
+    }
+}
+
+impl<const N: usize> Write for LineBuffer<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(N - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        self.truncated |= n < s.len();
+        Ok(())
+    }
+}
+
+/// Any enabled UART, written to before returning. See `dma_log::DmaUart`
+/// for the non-blocking version.
+impl<D: UartDevice, P: ValidUartPinout<D>> LogSink for UartPeripheral<Enabled, D, P> {
+    fn write(&mut self, record: &[u8]) {
+        // Waiting for the UART isn't a hang, see `guard`.
+        for chunk in record.chunks(32) {
+            self.write_full_blocking(chunk);
+            crate::guard::feed();
+        }
+    }
+
+    fn flush(&mut self) {
+        while self.uart_is_busy() {}
+    }
+}
+
+const MAX_SINKS: usize = 4;
+
+struct Slot {
+    sink: Option<&'static mut dyn LogSink>,
+    /// The core using the sink, it's out of the slot meanwhile.
+    user: Option<u8>,
+}
+
+/// The core running this, 0 or 1.
+pub fn core_id() -> u8 {
+    unsafe { (*rp2040_pac::SIO::PTR).cpuid().read().bits() as u8 }
+}
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
-pub enum LogSourcePath {
-    Enabled,
-    Disabled,
-}
-
-pub type Uart = UartPeripheral<
-    rp2040_hal::uart::Enabled,
-    rp2040_pac::UART0,
-    (
-        rp2040_hal::gpio::Pin<
-            rp2040_hal::gpio::bank0::Gpio0,
-            FunctionUart,
-            rp2040_hal::gpio::PullDown,
-        >,
-        rp2040_hal::gpio::Pin<
-            rp2040_hal::gpio::bank0::Gpio1,
-            FunctionUart,
-            rp2040_hal::gpio::PullDown,
-        >,
-    ),
->;
-
-pub struct UartLoggerInner {
-    uart: RefCell<Uart>,
-    log_source_path: LogSourcePath,
-    color: bool,
-}
-
-pub struct UartLogger {
-    uart: Option<UartLoggerInner>,
-}
-
-impl UartLogger {
+pub struct Logger {
+    /// Every record goes to all of them.
+    sinks: Mutex<RefCell<[Slot; MAX_SINKS]>>,
+    format: Mutex<Cell<LineFormat>>,
+    /// When the previous record was logged, for `Timestamp::Delta`.
+    previous_us: Mutex<Cell<u64>>,
+    filter: Mutex<RefCell<Filter>>,
+}
+
+impl Logger {
     pub const fn null() -> Self {
-        UartLogger { uart: None }
+        Logger {
+            sinks: Mutex::new(RefCell::new(
+                [const {
+                    Slot {
+                        sink: None,
+                        user: None,
+                    }
+                }; MAX_SINKS],
+            )),
+            format: Mutex::new(Cell::new(LineFormat::bare())),
+            previous_us: Mutex::new(Cell::new(0)),
+            filter: Mutex::new(RefCell::new(Filter::new())),
+        }
+    }
+
+    /// Returns `false` if there are too many sinks already.
+    fn add_sink(&self, sink: &'static mut dyn LogSink) -> bool {
+        critical_section::with(|cs| {
+            let mut slots = self.sinks.borrow_ref_mut(cs);
+            match slots
+                .iter_mut()
+                .find(|s| s.sink.is_none() && s.user.is_none())
+            {
+                Some(slot) => {
+                    slot.sink = Some(sink);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// Take the sink in slot `i` for this core. `None` if there's none or
+    /// this core is using it already.
+    fn claim(&self, i: usize) -> Option<&'static mut dyn LogSink> {
+        let core = core_id();
+        loop {
+            let claimed = critical_section::with(|cs| {
+                let slot = &mut self.sinks.borrow_ref_mut(cs)[i];
+                match slot.user {
+                    None => {
+                        let sink = slot.sink.take();
+                        if sink.is_some() {
+                            slot.user = Some(core);
+                        }
+                        Some(sink)
+                    }
+                    Some(user) if user == core => Some(None),
+                    // The other core has it.
+                    Some(_) => None,
+                }
+            });
+            if let Some(sink) = claimed {
+                return sink;
+            }
+        }
     }
 
-    pub fn set(&mut self, uart: Uart, log_source_path: LogSourcePath, color: bool) {
-        self.uart = Some(UartLoggerInner {
-            uart: RefCell::new(uart),
-            log_source_path,
-            color,
+    fn release(&self, i: usize, sink: &'static mut dyn LogSink) {
+        critical_section::with(|cs| {
+            let slot = &mut self.sinks.borrow_ref_mut(cs)[i];
+            slot.sink = Some(sink);
+            slot.user = None;
         });
     }
+
+    /// Run `f` on every sink, outside of any critical section.
+    fn each_sink(&self, mut f: impl FnMut(&mut dyn LogSink)) {
+        for i in 0..MAX_SINKS {
+            if let Some(sink) = self.claim(i) {
+                f(&mut *sink);
+                self.release(i, sink);
+            }
+        }
+    }
+
+    fn write(&self, record: &[u8]) {
+        self.each_sink(|sink| sink.write(record));
+    }
 }
 
-unsafe impl Send for UartLogger {}
-unsafe impl Sync for UartLogger {}
+// The sinks are only taken out of their slots in critical sections, and
+// used by one core at a time.
+unsafe impl Send for Logger {}
+unsafe impl Sync for Logger {}
 
-impl log::Log for UartLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let level =
+            critical_section::with(|cs| self.filter.borrow_ref(cs).level_for(metadata.target()));
+        metadata.level() <= level
     }
 
     fn log(&self, record: &log::Record) {
-        if self.uart.is_none() {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Framed for the host decoder, see `blog`.
+        if cfg!(feature = "binary-log") {
+            let mut message = LineBuffer::<LINE_SIZE>::new();
+            message.write_fmt(*record.args()).ok();
+            crate::blog::emit_text(
+                record.level(),
+                record.module_path().unwrap_or_default(),
+                record.file().unwrap_or_default(),
+                record.line().unwrap_or_default(),
+                message.as_bytes(),
+            );
             return;
         }
-        let mut uart = self.uart.as_ref().unwrap().uart.borrow_mut();
-        let log_source_path = self.uart.as_ref().unwrap().log_source_path;
-        let color = self.uart.as_ref().unwrap().color;
+
+        let time_us = crate::time::time_us64();
+        let (format, previous_us) = critical_section::with(|cs| {
+            (
+                self.format.borrow(cs).get(),
+                self.previous_us.borrow(cs).replace(time_us),
+            )
+        });
+        let color = format.color;
         let vte_color = match record.level() {
             log::Level::Trace => "\x1b[37m",
             log::Level::Debug => "\x1b[36m",
@@ -73,47 +236,111 @@ impl log::Log for UartLogger {
             log::Level::Warn => "\x1b[33m",
             log::Level::Error => "\x1b[31m",
         };
+
+        let mut line = LineBuffer::<LINE_SIZE>::new();
         if color {
-            uart.write_str(vte_color).ok();
+            line.write_str(vte_color).ok();
         }
 
-        uart.write_fmt(format_args!(
-            "{:08x}:[{:7}][{}",
-            crate::time::time_us64(),
-            record.level(),
-            record.module_path().unwrap_or_default(),
+        line.write_fmt(format_args!(
+            "{}{}",
+            Prefix {
+                format: &format,
+                time_us,
+                delta_us: time_us.saturating_sub(previous_us),
+                core: core_id(),
+                level: record.level(),
+                module: record.module_path().unwrap_or_default(),
+                file: record.file().unwrap_or_default(),
+                line: record.line().unwrap_or_default(),
+            },
+            record.args()
         ))
         .ok();
-        match log_source_path {
-            LogSourcePath::Enabled => {
-                uart.write_fmt(format_args!(
-                    "{}@{}",
-                    record.file().unwrap_or_default(),
-                    record.line().unwrap_or_default(),
-                ))
-                .ok();
-            }
-            LogSourcePath::Disabled => {}
-        }
-        uart.write_fmt(format_args!("] {}", record.args())).ok();
-        uart.write_str("\r\n").ok();
 
-        // Reset color
-        if color {
-            uart.write_str("\x1b[0m").ok();
-        }
+        // Reset color before the line ends, so whatever comes next, e.g. a
+        // raw `report` line, starts clean.
+        line.finish(if color { "\x1b[0m\r\n" } else { "\r\n" });
+
+        self.write(line.as_bytes());
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        self.each_sink(|sink| sink.flush());
+    }
 }
 
-static mut UART_LOGGER: UartLogger = UartLogger::null();
+static LOGGER: Logger = Logger::null();
 
-pub fn init_uart_log(uart: Uart, log_source_path: LogSourcePath, color: bool) {
-    #[allow(static_mut_refs)]
+/// Start logging to `sink`, more can be added with `add_log_sink`. See
+/// `log_format` and `log_filter` for the descriptions, bad ones are
+/// logged and replaced by the defaults.
+pub fn init_log(sink: &'static mut dyn LogSink, format: &str, filter: &str) {
+    let filter = Filter::parse(filter);
+    let format = LineFormat::parse(format);
+    set_log_format(
+        format
+            .or_else(|_| LineFormat::parse(crate::log_format::DEFAULT_SPEC))
+            .unwrap_or(LineFormat::bare()),
+    );
+    LOGGER.add_sink(sink);
+    // There's no compare-and-swap on the M0+ for the safe versions. This
+    // runs once at boot, before anything else could log.
     unsafe {
-        UART_LOGGER.set(uart, log_source_path, color);
-        log::set_logger_racy(&UART_LOGGER).unwrap();
+        log::set_logger_racy(&LOGGER).unwrap();
         log::set_max_level_racy(log::LevelFilter::Trace);
     }
+
+    if let Err(e) = format {
+        log::error!(
+            "Bad log format, using {:?}: {:?}",
+            crate::log_format::DEFAULT_SPEC,
+            e
+        );
+    }
+    match filter {
+        Ok(filter) => set_filter(filter),
+        Err(e) => log::error!("Bad log filter, logging everything: {:?}", e),
+    }
+}
+
+/// Send the records to `sink` too. Returns `false` if there are too many.
+pub fn add_log_sink(sink: &'static mut dyn LogSink) -> bool {
+    LOGGER.add_sink(sink)
+}
+
+/// Write an already encoded record, e.g. a `blog` frame, to all sinks
+/// bypassing the formatting and the filter.
+pub fn write_record(record: &[u8]) {
+    LOGGER.write(record);
+}
+
+/// Replace the log filter, see `log_filter` for the syntax.
+pub fn set_log_filter(spec: &str) -> Result<(), FilterError> {
+    set_filter(Filter::parse(spec)?);
+    Ok(())
+}
+
+fn set_filter(filter: Filter) {
+    critical_section::with(|cs| {
+        // Racy only with other writers, and they are excluded here.
+        unsafe { log::set_max_level_racy(filter.max_level()) };
+        *LOGGER.filter.borrow_ref_mut(cs) = filter;
+    });
+}
+
+/// Change how lines are laid out, see `log_format`.
+pub fn set_log_format(format: LineFormat) {
+    critical_section::with(|cs| LOGGER.format.borrow(cs).set(format));
+}
+
+/// Log the filter in effect.
+pub fn log_filter() {
+    let filter = critical_section::with(|cs| LOGGER.filter.borrow_ref(cs).clone());
+    filter.log();
+}
+
+/// Log the line format in effect.
+pub fn log_format() {
+    critical_section::with(|cs| LOGGER.format.borrow(cs).get()).log();
 }